@@ -0,0 +1,56 @@
+//! Overhead of resolving short links in `AppState::get_url_by_id`, measured against the
+//! in-memory store so that no database round trip blurs the numbers.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use tinyurl::{
+    store::{InMemoryStore, UrlStore},
+    AppState, Config, ShortenRequest,
+};
+use tokio::runtime::Runtime;
+
+/// Stores `url` under `id`, with the other fields of `options` as JSON.
+async fn shorten(store: &InMemoryStore, id: &str, url: &str, options: &str) {
+    let req: ShortenRequest =
+        serde_json::from_str(&format!(r#"{{"url": "{}"{}}}"#, url, options)).unwrap();
+    store.shorten(id, url, &req).await.unwrap();
+}
+
+async fn app_state() -> AppState<InMemoryStore> {
+    let store = InMemoryStore::new();
+    shorten(&store, "cached", "https://example.com/cached", "").await;
+    // limited links bypass the cache, so every redirect goes to the store
+    shorten(
+        &store,
+        "uncached",
+        "https://example.com/uncached",
+        r#", "max_clicks": 2147483647"#,
+    )
+    .await;
+
+    AppState::new(store, &Config::from_env()).await.unwrap()
+}
+
+fn redirect(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let state = rt.block_on(app_state());
+    rt.block_on(state.get_url_by_id("cached", None, None))
+        .unwrap();
+
+    let mut group = c.benchmark_group("get_url_by_id");
+    group.bench_function("cache_hit", |b| {
+        b.to_async(&rt)
+            .iter(|| state.get_url_by_id("cached", None, None))
+    });
+    group.bench_function("cache_miss", |b| {
+        b.to_async(&rt)
+            .iter(|| state.get_url_by_id("uncached", None, None))
+    });
+    group.bench_function("not_found", |b| {
+        b.to_async(&rt)
+            .iter(|| state.get_url_by_id("unknown", None, None))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, redirect);
+criterion_main!(benches);
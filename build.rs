@@ -0,0 +1,9 @@
+fn main() {
+    // re-embed migrations when a new one is added
+    println!("cargo:rerun-if-changed=migrations");
+    println!("cargo:rerun-if-changed=migrations_sqlite");
+
+    if std::env::var_os("CARGO_CFG_UNIX").is_none() {
+        println!("cargo:warning=TINYURL_UNIX_SOCKET is not supported on this target");
+    }
+}
@@ -0,0 +1,25 @@
+//! Feeds arbitrary bytes to `normalize_url`, which must never panic, must refuse overlong
+//! input before parsing it, and must only accept http(s) URLs with a host.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tinyurl::{normalize_url, MAX_URL_LENGTH};
+
+fuzz_target!(|data: &[u8]| {
+    // requests are JSON, so bodies that are not UTF-8 are rejected before validation
+    let Ok(input) = std::str::from_utf8(data) else {
+        return;
+    };
+
+    if let Ok(url) = normalize_url(input) {
+        assert!(matches!(url.scheme(), "http" | "https"), "{}", url);
+        assert!(
+            url.host_str().is_some_and(|host| !host.is_empty()),
+            "{}",
+            url
+        );
+        assert!(input.trim().chars().count() <= MAX_URL_LENGTH);
+        assert!(!input.contains('\0'));
+    }
+});
@@ -0,0 +1,2 @@
+-- allow configurable and vanity short codes up to 32 characters
+ALTER TABLE urls ALTER COLUMN id TYPE VARCHAR(32);
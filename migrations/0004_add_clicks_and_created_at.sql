@@ -0,0 +1,3 @@
+ALTER TABLE urls
+ADD COLUMN IF NOT EXISTS clicks BIGINT NOT NULL DEFAULT 0,
+ADD COLUMN IF NOT EXISTS created_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
@@ -0,0 +1,5 @@
+ALTER TABLE urls ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+
+-- only live rows need a unique url, so a deleted url can be shortened again
+ALTER TABLE urls DROP CONSTRAINT IF EXISTS urls_url_key;
+CREATE UNIQUE INDEX IF NOT EXISTS urls_url_live_key ON urls (url) WHERE deleted_at IS NULL;
@@ -0,0 +1,2 @@
+-- 0 = permanent (308), 1 = temporary (307)
+ALTER TABLE urls ADD COLUMN IF NOT EXISTS redirect_type SMALLINT NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+-- NULL means the link can be followed any number of times
+ALTER TABLE urls ADD COLUMN IF NOT EXISTS max_clicks INTEGER;
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    key UUID PRIMARY KEY,
+    request TEXT NOT NULL,
+    url_id VARCHAR(32) NOT NULL REFERENCES urls (id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,10 @@
+-- no foreign key on url_id: entries outlive the links they describe, purges included
+CREATE TABLE IF NOT EXISTS audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    action TEXT NOT NULL,
+    url_id VARCHAR(32) NOT NULL,
+    actor TEXT NOT NULL,
+    timestamp TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_url_id_timestamp_idx ON audit_log (url_id, timestamp);
@@ -0,0 +1,7 @@
+-- reserved codes have no destination yet (url = '') until they are activated
+ALTER TABLE urls ADD COLUMN IF NOT EXISTS reserved_until TIMESTAMPTZ;
+
+-- reservations all share the empty url, so only activated rows need a unique one
+DROP INDEX IF EXISTS urls_url_live_key;
+CREATE UNIQUE INDEX IF NOT EXISTS urls_url_live_key ON urls (url)
+WHERE deleted_at IS NULL AND reserved_until IS NULL;
@@ -0,0 +1,4 @@
+ALTER TABLE urls ADD COLUMN IF NOT EXISTS tags TEXT[] NOT NULL DEFAULT '{}';
+
+-- serves the `tags @> ARRAY[...]` containment filter of the admin listing
+CREATE INDEX IF NOT EXISTS urls_tags_idx ON urls USING GIN (tags);
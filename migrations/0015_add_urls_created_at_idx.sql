@@ -0,0 +1,3 @@
+-- serves the keyset pagination of the admin listing, newest first
+CREATE INDEX IF NOT EXISTS urls_live_created_at_idx ON urls (created_at DESC, id DESC)
+    WHERE deleted_at IS NULL AND reserved_until IS NULL;
@@ -0,0 +1,10 @@
+-- HTTP callbacks notified of link events, optionally only for one short code
+CREATE TABLE IF NOT EXISTS webhooks (
+    id BIGSERIAL PRIMARY KEY,
+    target_url TEXT NOT NULL,
+    event TEXT NOT NULL CHECK (event IN ('created', 'clicked')),
+    url_id TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS webhooks_event_idx ON webhooks (event);
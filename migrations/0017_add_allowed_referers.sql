@@ -0,0 +1,2 @@
+-- origins a link may be followed from; empty allows any referer
+ALTER TABLE urls ADD COLUMN IF NOT EXISTS allowed_referers TEXT[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,2 @@
+-- bcrypt hash of the password visitors must give before being redirected
+ALTER TABLE urls ADD COLUMN IF NOT EXISTS password_hash TEXT;
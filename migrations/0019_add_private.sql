@@ -0,0 +1,2 @@
+-- private links are left out of the sitemap
+ALTER TABLE urls ADD COLUMN IF NOT EXISTS private BOOLEAN NOT NULL DEFAULT FALSE;
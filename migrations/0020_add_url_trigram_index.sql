@@ -0,0 +1,3 @@
+-- lets `GET /admin/urls/search` match `url ILIKE '%...%'` without scanning the whole table
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+CREATE INDEX IF NOT EXISTS urls_url_trgm_idx ON urls USING GIN (url gin_trgm_ops);
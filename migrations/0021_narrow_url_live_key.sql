@@ -0,0 +1,5 @@
+-- links that expire or run out of clicks would keep blocking their url once dead,
+-- so only the ones that stay live for good need a unique one
+DROP INDEX IF EXISTS urls_url_live_key;
+CREATE UNIQUE INDEX IF NOT EXISTS urls_url_live_key ON urls (url)
+WHERE deleted_at IS NULL AND reserved_until IS NULL AND expires_at IS NULL AND max_clicks IS NULL;
@@ -0,0 +1,11 @@
+-- only plain links are shared between requests for the same url, so protected, limited
+-- or private ones never block it, and each redirect type keeps its own code
+DROP INDEX IF EXISTS urls_url_live_key;
+CREATE UNIQUE INDEX IF NOT EXISTS urls_url_live_key ON urls (url, redirect_type)
+WHERE deleted_at IS NULL
+  AND reserved_until IS NULL
+  AND expires_at IS NULL
+  AND max_clicks IS NULL
+  AND password_hash IS NULL
+  AND cardinality(allowed_referers) = 0
+  AND NOT private;
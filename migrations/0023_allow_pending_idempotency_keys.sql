@@ -0,0 +1,2 @@
+-- a key is claimed before its request runs, so it has no link until that finishes
+ALTER TABLE idempotency_keys ALTER COLUMN url_id DROP NOT NULL;
@@ -0,0 +1,27 @@
+-- the schema of `migrations/` up to 0008 in the SQLite dialect: timestamps are UTC text as
+-- written by `strftime('%Y-%m-%d %H:%M:%f')`, which sorts chronologically; later changes
+-- share the number of their Postgres migration
+CREATE TABLE IF NOT EXISTS urls (
+    id TEXT PRIMARY KEY,
+    url TEXT NOT NULL,
+    expires_at TEXT,
+    clicks INTEGER NOT NULL DEFAULT 0,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+    deleted_at TEXT,
+    -- 0 = permanent (308), 1 = temporary (307)
+    redirect_type INTEGER NOT NULL DEFAULT 0,
+    max_clicks INTEGER
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS urls_url_live_key ON urls (url) WHERE deleted_at IS NULL;
+
+CREATE TABLE IF NOT EXISTS clicks (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    url_id TEXT NOT NULL REFERENCES urls (id) ON DELETE CASCADE,
+    clicked_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+    ip_address TEXT,
+    user_agent TEXT,
+    referer TEXT
+);
+
+CREATE INDEX IF NOT EXISTS clicks_url_id_clicked_at_idx ON clicks (url_id, clicked_at);
@@ -0,0 +1,10 @@
+-- no foreign key on url_id: entries outlive the links they describe, purges included
+CREATE TABLE IF NOT EXISTS audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    action TEXT NOT NULL,
+    url_id TEXT NOT NULL,
+    actor TEXT NOT NULL,
+    timestamp TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now'))
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_url_id_timestamp_idx ON audit_log (url_id, timestamp);
@@ -0,0 +1,6 @@
+-- bearer tokens with their own rate limit; anonymous clients get TINYURL_RATE_LIMIT
+CREATE TABLE IF NOT EXISTS api_keys (
+    key TEXT PRIMARY KEY,
+    requests_per_minute INTEGER NOT NULL CHECK (requests_per_minute >= 0),
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now'))
+);
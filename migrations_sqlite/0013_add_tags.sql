@@ -0,0 +1,2 @@
+-- a JSON array of strings, matched with `json_each` by the admin listing
+ALTER TABLE urls ADD COLUMN tags TEXT NOT NULL DEFAULT '[]';
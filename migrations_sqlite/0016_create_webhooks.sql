@@ -0,0 +1,10 @@
+-- HTTP callbacks notified of link events, optionally only for one short code
+CREATE TABLE IF NOT EXISTS webhooks (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    target_url TEXT NOT NULL,
+    event TEXT NOT NULL CHECK (event IN ('created', 'clicked')),
+    url_id TEXT,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now'))
+);
+
+CREATE INDEX IF NOT EXISTS webhooks_event_idx ON webhooks (event);
@@ -0,0 +1,2 @@
+-- origins a link may be followed from, as a JSON array; empty allows any referer
+ALTER TABLE urls ADD COLUMN allowed_referers TEXT NOT NULL DEFAULT '[]';
@@ -0,0 +1,2 @@
+-- private links are left out of the sitemap
+ALTER TABLE urls ADD COLUMN private BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,12 @@
+-- a key is claimed before its request runs, so it has no link until that finishes;
+-- SQLite cannot drop NOT NULL from a column, so the table is rebuilt without it
+CREATE TABLE idempotency_keys_pending (
+    key TEXT PRIMARY KEY,
+    request TEXT NOT NULL,
+    url_id TEXT REFERENCES urls (id) ON DELETE CASCADE,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now'))
+);
+INSERT INTO idempotency_keys_pending (key, request, url_id, created_at)
+SELECT key, request, url_id, created_at FROM idempotency_keys;
+DROP TABLE idempotency_keys;
+ALTER TABLE idempotency_keys_pending RENAME TO idempotency_keys;
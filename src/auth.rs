@@ -0,0 +1,117 @@
+use std::net::SocketAddr;
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{request::Parts, HeaderMap},
+};
+use http::header;
+use subtle::ConstantTimeEq;
+
+use crate::{store::UrlStore, AppState, TinyUrlError};
+
+/// Extractor guarding admin endpoints with the `TINYURL_ADMIN_TOKEN` bearer token.
+///
+/// Admin endpoints are disabled entirely when no token is configured.
+pub struct RequireAdmin;
+
+#[async_trait]
+impl<S: UrlStore> FromRequestParts<AppState<S>> for RequireAdmin {
+    type Rejection = TinyUrlError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState<S>,
+    ) -> Result<Self, Self::Rejection> {
+        let expected = state
+            .config
+            .admin_token
+            .as_deref()
+            .ok_or(TinyUrlError::Unauthorized)?;
+
+        match bearer_token(&parts.headers) {
+            Some(token) if bool::from(token.as_bytes().ct_eq(expected.as_bytes())) => Ok(Self),
+            _ => Err(TinyUrlError::Unauthorized),
+        }
+    }
+}
+
+/// Extractor guarding the mutating endpoints with the `TINYURL_API_TOKEN` bearer token.
+///
+/// Every request passes when no token is configured. The admin token is accepted as well,
+/// so operators do not need to juggle two credentials, and so are keys in `api_keys`.
+pub struct RequireAuth;
+
+#[async_trait]
+impl<S: UrlStore> FromRequestParts<AppState<S>> for RequireAuth {
+    type Rejection = TinyUrlError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState<S>,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(expected) = state.config.api_token.as_deref() else {
+            return Ok(Self);
+        };
+
+        let token = bearer_token(&parts.headers).ok_or(TinyUrlError::Unauthorized)?;
+        let matches = |secret: &str| bool::from(token.as_bytes().ct_eq(secret.as_bytes()));
+
+        if matches(expected) || state.config.admin_token.as_deref().is_some_and(matches) {
+            return Ok(Self);
+        }
+
+        let ConnectInfo(addr) = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .ok_or(TinyUrlError::Unauthorized)?;
+        match state
+            .rate_limiter
+            .key_limit(&state.store, token, addr.ip())
+            .await?
+        {
+            Some(_) => Ok(Self),
+            None => Err(TinyUrlError::Unauthorized),
+        }
+    }
+}
+
+/// Extractor naming who makes a request, for the audit log: `admin` or `api` for holders of
+/// those tokens, the client IP otherwise.
+pub struct Actor(pub String);
+
+#[async_trait]
+impl<S: UrlStore> FromRequestParts<AppState<S>> for Actor {
+    type Rejection = TinyUrlError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState<S>,
+    ) -> Result<Self, Self::Rejection> {
+        if let Some(token) = bearer_token(&parts.headers) {
+            let matches = |secret: &str| bool::from(token.as_bytes().ct_eq(secret.as_bytes()));
+
+            if state.config.admin_token.as_deref().is_some_and(matches) {
+                return Ok(Self("admin".to_string()));
+            }
+            if state.config.api_token.as_deref().is_some_and(matches) {
+                return Ok(Self("api".to_string()));
+            }
+        }
+
+        let ip = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string());
+
+        Ok(Self(ip.unwrap_or_else(|| "unknown".to_string())))
+    }
+}
+
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
@@ -0,0 +1,112 @@
+//! Redirect lookup cache: an in-process LRU by default, or Redis shared across
+//! instances with the `redis-cache` feature.
+
+#[cfg(feature = "redis-cache")]
+use chrono::Utc;
+#[cfg(not(feature = "redis-cache"))]
+use moka::future::Cache;
+#[cfg(feature = "redis-cache")]
+use redis::{aio::ConnectionManager, AsyncCommands};
+#[cfg(feature = "redis-cache")]
+use tracing::warn;
+
+use crate::{Config, RedirectTarget, TinyUrlError};
+
+#[cfg(not(feature = "redis-cache"))]
+#[derive(Debug, Clone)]
+pub struct RedirectCache {
+    inner: Cache<String, RedirectTarget>,
+}
+
+#[cfg(not(feature = "redis-cache"))]
+impl RedirectCache {
+    pub async fn new(config: &Config) -> Result<Self, TinyUrlError> {
+        Ok(Self {
+            inner: Cache::new(config.cache_capacity),
+        })
+    }
+
+    pub async fn get(&self, id: &str) -> Option<RedirectTarget> {
+        self.inner.get(id).await
+    }
+
+    pub async fn insert(&self, id: &str, target: &RedirectTarget) {
+        self.inner.insert(id.to_string(), target.clone()).await;
+    }
+
+    pub async fn invalidate(&self, id: &str) {
+        self.inner.invalidate(id).await;
+    }
+}
+
+#[cfg(feature = "redis-cache")]
+#[derive(Clone)]
+pub struct RedirectCache {
+    conn: ConnectionManager,
+}
+
+#[cfg(feature = "redis-cache")]
+impl std::fmt::Debug for RedirectCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedirectCache").finish_non_exhaustive()
+    }
+}
+
+/// Redis failures are logged and treated as misses so the database stays authoritative.
+#[cfg(feature = "redis-cache")]
+impl RedirectCache {
+    pub async fn new(config: &Config) -> Result<Self, TinyUrlError> {
+        let client = redis::Client::open(config.redis_url.as_str())?;
+        let conn = ConnectionManager::new(client).await?;
+
+        Ok(Self { conn })
+    }
+
+    pub async fn get(&self, id: &str) -> Option<RedirectTarget> {
+        let mut conn = self.conn.clone();
+        let value: Option<String> = conn
+            .get(key(id))
+            .await
+            .inspect_err(|e| warn!("Redis GET failed for {}: {}", id, e))
+            .ok()?;
+
+        serde_json::from_str(&value?).ok()
+    }
+
+    pub async fn insert(&self, id: &str, target: &RedirectTarget) {
+        let Ok(value) = serde_json::to_string(target) else {
+            return;
+        };
+        let mut conn = self.conn.clone();
+
+        // let redis expire the entry together with the link itself
+        let res: redis::RedisResult<()> = match target.expires_at {
+            Some(at) => {
+                let ttl = (at - Utc::now()).num_seconds();
+                if ttl <= 0 {
+                    return;
+                }
+                conn.set_ex(key(id), value, ttl as u64).await
+            }
+            None => conn.set(key(id), value).await,
+        };
+
+        if let Err(e) = res {
+            warn!("Redis SET failed for {}: {}", id, e);
+        }
+    }
+
+    pub async fn invalidate(&self, id: &str) {
+        let mut conn = self.conn.clone();
+        let res: redis::RedisResult<()> = conn.del(key(id)).await;
+
+        if let Err(e) = res {
+            warn!("Redis DEL failed for {}: {}", id, e);
+        }
+    }
+}
+
+#[cfg(feature = "redis-cache")]
+fn key(id: &str) -> String {
+    format!("tinyurl:{}", id)
+}
@@ -0,0 +1,102 @@
+//! Client subcommands talking to a running server, behind the `cli` feature.
+
+use std::env;
+
+use clap::{Parser, Subcommand};
+use reqwest::{header, redirect::Policy, Client, RequestBuilder, Response};
+use serde_json::json;
+
+use crate::{ShortenResponse, TinyUrlError};
+
+const DEFAULT_SERVER_URL: &str = "http://127.0.0.1:9876";
+
+#[derive(Debug, Parser)]
+#[command(version, about = "URL shortener server and client")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Start the HTTP server (the default).
+    Serve,
+    /// Shorten a URL and print the short link.
+    Shorten { url: String },
+    /// Print the destination of a short code without counting a click.
+    Redirect { id: String },
+    /// Delete a short code (needs `TINYURL_ADMIN_TOKEN`).
+    Delete { id: String },
+}
+
+/// Parses the command line, returning the client command to run, or `None` to serve.
+pub fn client_command() -> Option<Command> {
+    match Cli::parse().command {
+        None | Some(Command::Serve) => None,
+        command => command,
+    }
+}
+
+/// Runs `command` against the server at `TINYURL_SERVER_URL`.
+///
+/// `TINYURL_API_TOKEN` and `TINYURL_ADMIN_TOKEN` are sent as bearer tokens when set.
+pub async fn run(command: Command) -> Result<(), TinyUrlError> {
+    let server = env::var("TINYURL_SERVER_URL")
+        .map(|url| url.trim_end_matches('/').to_string())
+        .unwrap_or_else(|_| DEFAULT_SERVER_URL.to_string());
+    let client = Client::builder()
+        .redirect(Policy::none())
+        .build()
+        .map_err(client_error)?;
+
+    match command {
+        Command::Serve => unreachable!("serve is handled by main"),
+        Command::Shorten { url } => {
+            let req = client
+                .post(format!("{}/", server))
+                .json(&json!({ "url": url }));
+            let resp = send(with_token(req, "TINYURL_API_TOKEN")).await?;
+            let body: ShortenResponse = resp.json().await.map_err(client_error)?;
+            println!("{}", body.url);
+        }
+        Command::Redirect { id } => {
+            let resp = send(client.head(format!("{}/{}", server, id))).await?;
+            let location = resp
+                .headers()
+                .get(header::LOCATION)
+                .and_then(|v| v.to_str().ok())
+                .ok_or_else(|| TinyUrlError::ClientError("no Location header".to_string()))?;
+            println!("{}", location);
+        }
+        Command::Delete { id } => {
+            let req = client.delete(format!("{}/{}", server, id));
+            send(with_token(req, "TINYURL_ADMIN_TOKEN")).await?;
+            println!("Deleted {}", id);
+        }
+    }
+
+    Ok(())
+}
+
+fn with_token(req: RequestBuilder, var: &str) -> RequestBuilder {
+    match env::var(var) {
+        Ok(token) if !token.is_empty() => req.bearer_auth(token),
+        _ => req,
+    }
+}
+
+/// Sends `req`, turning error statuses into `ClientError` with the server's message.
+async fn send(req: RequestBuilder) -> Result<Response, TinyUrlError> {
+    let resp = req.send().await.map_err(client_error)?;
+    let status = resp.status();
+    if status.is_client_error() || status.is_server_error() {
+        let body = resp.text().await.unwrap_or_default();
+        return Err(TinyUrlError::ClientError(format!("{}: {}", status, body)));
+    }
+
+    Ok(resp)
+}
+
+fn client_error(e: reqwest::Error) -> TinyUrlError {
+    TinyUrlError::ClientError(e.to_string())
+}
@@ -0,0 +1,161 @@
+//! Optional TOML file holding the settings otherwise read from the environment.
+
+use std::{collections::HashMap, env, fs};
+
+use serde::Deserialize;
+
+use crate::TinyUrlError;
+
+/// Contents of the file named by `TINYURL_CONFIG`, keyed by the names of the `Config`
+/// fields. Durations are numbers in the unit of the matching variable, e.g. milliseconds for
+/// `request_timeout` as for `TINYURL_REQUEST_TIMEOUT_MS`, and lists are arrays of strings.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    log_level: Option<String>,
+    log_format: Option<String>,
+    host: Option<String>,
+    port: Option<u16>,
+    listen_addr: Option<String>,
+    base_url: Option<String>,
+    database_url: Option<String>,
+    db_read_url: Option<String>,
+    db_max_connections: Option<u32>,
+    db_connect_timeout: Option<u64>,
+    request_timeout: Option<u64>,
+    slow_query_threshold: Option<u64>,
+    code_length: Option<usize>,
+    id_alphabet: Option<String>,
+    blocked_domains: Option<Vec<String>>,
+    allowed_domains: Option<Vec<String>>,
+    reservation_ttl: Option<u64>,
+    cleanup_interval: Option<u64>,
+    max_retries: Option<u8>,
+    retry_base_delay: Option<u64>,
+    rate_limit: Option<u64>,
+    admin_token: Option<String>,
+    api_token: Option<String>,
+    anonymize_ips: Option<bool>,
+    cache_capacity: Option<u64>,
+    redis_url: Option<String>,
+    unix_socket: Option<String>,
+    tls_cert: Option<String>,
+    tls_key: Option<String>,
+    tls_port: Option<u16>,
+    cors_origins: Option<Vec<String>>,
+    cors_max_age: Option<u64>,
+}
+
+impl FileConfig {
+    /// The settings as the values of the variables they stand in for.
+    fn into_vars(self) -> HashMap<&'static str, String> {
+        let list = |list: Option<Vec<String>>| list.map(|list| list.join(","));
+        let settings = [
+            ("TINYURL_LOG_LEVEL", text(self.log_level)),
+            ("TINYURL_LOG_FORMAT", text(self.log_format)),
+            ("TINYURL_HOST", text(self.host)),
+            ("TINYURL_PORT", text(self.port)),
+            ("TINYURL_LISTEN_ADDR", text(self.listen_addr)),
+            ("TINYURL_BASE_URL", text(self.base_url)),
+            ("DATABASE_URL", text(self.database_url)),
+            ("TINYURL_DB_READ_ADDR", text(self.db_read_url)),
+            ("TINYURL_DB_MAX_CONNECTIONS", text(self.db_max_connections)),
+            (
+                "TINYURL_DB_CONNECT_TIMEOUT_SECS",
+                text(self.db_connect_timeout),
+            ),
+            ("TINYURL_REQUEST_TIMEOUT_MS", text(self.request_timeout)),
+            (
+                "TINYURL_SLOW_QUERY_THRESHOLD_MS",
+                text(self.slow_query_threshold),
+            ),
+            ("TINYURL_CODE_LENGTH", text(self.code_length)),
+            ("TINYURL_ID_ALPHABET", text(self.id_alphabet)),
+            ("TINYURL_BLOCKED_DOMAINS", list(self.blocked_domains)),
+            ("TINYURL_ALLOWED_DOMAINS", list(self.allowed_domains)),
+            ("TINYURL_RESERVATION_TTL_SECS", text(self.reservation_ttl)),
+            ("TINYURL_CLEANUP_INTERVAL_SECS", text(self.cleanup_interval)),
+            ("TINYURL_MAX_RETRIES", text(self.max_retries)),
+            ("TINYURL_RETRY_BASE_DELAY_MS", text(self.retry_base_delay)),
+            ("TINYURL_RATE_LIMIT", text(self.rate_limit)),
+            ("TINYURL_ADMIN_TOKEN", text(self.admin_token)),
+            ("TINYURL_API_TOKEN", text(self.api_token)),
+            ("TINYURL_ANONYMIZE_IPS", text(self.anonymize_ips)),
+            ("TINYURL_CACHE_CAPACITY", text(self.cache_capacity)),
+            ("TINYURL_REDIS_URL", text(self.redis_url)),
+            ("TINYURL_UNIX_SOCKET", text(self.unix_socket)),
+            ("TINYURL_TLS_CERT", text(self.tls_cert)),
+            ("TINYURL_TLS_KEY", text(self.tls_key)),
+            ("TINYURL_TLS_PORT", text(self.tls_port)),
+            ("TINYURL_CORS_ORIGINS", list(self.cors_origins)),
+            ("TINYURL_CORS_MAX_AGE", text(self.cors_max_age)),
+        ];
+
+        settings
+            .into_iter()
+            .filter_map(|(key, value)| Some((key, value?)))
+            .collect()
+    }
+}
+
+/// Spells a setting the way the variable would.
+fn text<T: ToString>(value: Option<T>) -> Option<String> {
+    value.map(|value| value.to_string())
+}
+
+/// Where settings are looked up: the environment first, then the config file.
+#[derive(Debug, Default)]
+pub(crate) struct Vars {
+    file: HashMap<&'static str, String>,
+    /// Stands in for the process environment, so that tests need not change it.
+    env: Option<HashMap<String, String>>,
+}
+
+impl Vars {
+    /// Reads the file named by `TINYURL_CONFIG`, if set, failing if it cannot be read or
+    /// parsed rather than running with defaults.
+    pub(crate) fn load() -> Result<Self, TinyUrlError> {
+        let Ok(path) = env::var("TINYURL_CONFIG") else {
+            return Ok(Self::default());
+        };
+
+        let text = fs::read_to_string(&path).map_err(|e| {
+            TinyUrlError::InvalidConfig(format!("cannot read config file {}: {}", path, e))
+        })?;
+        Self::parse(&text).map_err(|e| {
+            TinyUrlError::InvalidConfig(format!("malformed config file {}: {}", path, e))
+        })
+    }
+
+    pub(crate) fn parse(text: &str) -> Result<Self, toml::de::Error> {
+        let file: FileConfig = toml::from_str(text)?;
+
+        Ok(Self {
+            file: file.into_vars(),
+            env: None,
+        })
+    }
+
+    /// The same settings, looked up in `env` instead of the process environment.
+    #[cfg(test)]
+    pub(crate) fn with_env(self, env: &[(&str, &str)]) -> Self {
+        let env = env
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+
+        Self {
+            env: Some(env),
+            ..self
+        }
+    }
+
+    /// Value of the variable `key`, or else of the matching setting in the config file.
+    pub(crate) fn get(&self, key: &str) -> Option<String> {
+        let value = match &self.env {
+            Some(env) => env.get(key).cloned(),
+            None => env::var(key).ok(),
+        };
+        value.or_else(|| self.file.get(key).cloned())
+    }
+}
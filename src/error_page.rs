@@ -0,0 +1,62 @@
+//! HTML error page for browsers following short links that cannot be resolved, which would
+//! otherwise be shown the JSON error body.
+
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{Html, IntoResponse, Response},
+};
+
+use crate::preview;
+
+/// Whether the client asks for HTML ahead of JSON, as browsers do.
+pub fn prefers_html(headers: &HeaderMap) -> bool {
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+
+    match (accept.find("text/html"), accept.find("application/json")) {
+        (Some(html), Some(json)) => html < json,
+        (html, _) => html.is_some(),
+    }
+}
+
+/// The page for a link answered with `status`, with a link back to the service.
+pub fn response(status: StatusCode) -> Response {
+    let nonce = preview::nonce();
+    let mut resp = (status, Html(render(status, &nonce))).into_response();
+
+    let csp = format!("default-src 'none'; style-src 'nonce-{nonce}'");
+    resp.headers_mut().insert(
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::from_str(&csp).expect("nonce is hex"),
+    );
+
+    resp
+}
+
+fn render(status: StatusCode, nonce: &str) -> String {
+    let code = status.as_u16();
+    let reason = status.canonical_reason().unwrap_or_default();
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{code} {reason}</title>
+<style nonce="{nonce}">
+body {{ font-family: system-ui, sans-serif; max-width: 40em; margin: 4em auto; padding: 0 1em; }}
+h1 {{ font-size: 1.5em; }}
+</style>
+</head>
+<body>
+<h1>This short link does not exist</h1>
+<p>It may have been mistyped, deleted, or expired.</p>
+<p><a href="/">Go to the home page</a></p>
+</body>
+</html>
+"#
+    )
+}
@@ -0,0 +1,42 @@
+//! Best-effort detection of URLs that already redirect back to this service, behind the
+//! `loop-check` feature.
+
+use std::time::Duration;
+
+use reqwest::{header, redirect::Policy, Client};
+use url::Url;
+
+use crate::TinyUrlError;
+
+/// How long to wait for the destination to answer before giving up on the check.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone)]
+pub struct LoopChecker {
+    client: Client,
+}
+
+impl LoopChecker {
+    pub fn new() -> Result<Self, TinyUrlError> {
+        let client = Client::builder()
+            .redirect(Policy::none())
+            .timeout(CHECK_TIMEOUT)
+            .build()
+            .map_err(|e| TinyUrlError::InvalidConfig(e.to_string()))?;
+
+        Ok(Self { client })
+    }
+
+    /// Requests `url` once and returns where it redirects to, if anywhere.
+    ///
+    /// Unreachable destinations are not an error: the check only catches loops it can see.
+    pub async fn redirect_target(&self, url: &str) -> Option<Url> {
+        let resp = self.client.head(url).send().await.ok()?;
+        if !resp.status().is_redirection() {
+            return None;
+        }
+
+        let location = resp.headers().get(header::LOCATION)?.to_str().ok()?;
+        resp.url().join(location).ok()
+    }
+}
@@ -0,0 +1,99 @@
+use utoipa::{
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Modify, OpenApi,
+};
+
+use crate::{
+    AuditAction, AuditEntry, AuditList, BatchItem, BatchRequest, Click, ClickAdjustment,
+    ClickBucket, ClickCount, DomainStats, ErrorBody, ExportRecord, GlobalStats, HealthResponse,
+    ImportSummary, LookupResponse, PurgeResponse, RedirectType, ReserveRequest, ReserveResponse,
+    SearchHit, ServiceInfo, ShortenRequest, ShortenResponse, UpdateRequest, UrlList, UrlPage,
+    UrlRecord, Webhook, WebhookEvent, WebhookRequest,
+};
+
+/// OpenAPI document served at `/openapi.json`.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "TinyURL"),
+    paths(
+        crate::shorten,
+        crate::batch_shorten,
+        crate::reserve,
+        crate::lookup,
+        crate::redirect,
+        crate::check_exists,
+        crate::update_url,
+        crate::delete_url,
+        crate::restore_url,
+        crate::create_webhook,
+        crate::stats,
+        crate::global_stats,
+        crate::domain_stats,
+        crate::qr_code,
+        crate::list_clicks,
+        crate::adjust_clicks,
+        crate::click_timeseries,
+        crate::list_audit,
+        crate::export,
+        crate::sitemap,
+        crate::robots_txt,
+        crate::import,
+        crate::list_urls,
+        crate::search_urls,
+        crate::list_deleted_urls,
+        crate::purge_deleted,
+        crate::service_info,
+        crate::health_live,
+        crate::health_ready,
+    ),
+    components(schemas(
+        ShortenRequest,
+        RedirectType,
+        ShortenResponse,
+        BatchRequest,
+        BatchItem,
+        ReserveRequest,
+        ReserveResponse,
+        LookupResponse,
+        UpdateRequest,
+        UrlRecord,
+        UrlList,
+        UrlPage,
+        GlobalStats,
+        DomainStats,
+        SearchHit,
+        Click,
+        ClickBucket,
+        ClickAdjustment,
+        ClickCount,
+        AuditAction,
+        AuditEntry,
+        WebhookEvent,
+        WebhookRequest,
+        Webhook,
+        AuditList,
+        ExportRecord,
+        ImportSummary,
+        PurgeResponse,
+        ServiceInfo,
+        HealthResponse,
+        ErrorBody,
+    )),
+    modifiers(&BearerTokens)
+)]
+pub struct ApiDoc;
+
+/// Registers the `admin_token` and `api_token` bearer schemes referenced by the endpoints.
+struct BearerTokens;
+
+impl Modify for BearerTokens {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        for name in ["admin_token", "api_token"] {
+            components.add_security_scheme(
+                name,
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            );
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use axum::{
+    http::{header, HeaderValue},
+    response::{Html, IntoResponse, Response},
+};
+
+/// The interstitial page shown for `GET /:id?preview=1`, with headers that only allow it to
+/// be framed by this service, so other sites cannot overlay it to trick users into clicking.
+///
+/// Inline styles and scripts only run if they carry the nonce generated for this response,
+/// so markup injected into the page stays inert without resorting to `unsafe-inline`.
+pub fn response(id: &str, url: &str) -> Response {
+    let nonce = nonce();
+    let mut resp = Html(render(id, url, &nonce)).into_response();
+
+    let csp = format!(
+        "default-src 'none'; style-src 'nonce-{nonce}'; script-src 'nonce-{nonce}' 'strict-dynamic'; frame-ancestors 'self'"
+    );
+    let headers = resp.headers_mut();
+    headers.insert(
+        header::X_FRAME_OPTIONS,
+        HeaderValue::from_static("SAMEORIGIN"),
+    );
+    headers.insert(
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::from_str(&csp).expect("nonce is hex"),
+    );
+
+    resp
+}
+
+/// 128 random bits in hex, fresh for every response.
+pub fn nonce() -> String {
+    rand::random::<[u8; 16]>()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Renders the interstitial page, with `nonce` on its inline style.
+///
+/// The "Continue" link goes through the regular redirect so the click is counted.
+fn render(id: &str, url: &str, nonce: &str) -> String {
+    let id = escape_html(id);
+    let url = escape_html(url);
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="referrer" content="no-referrer">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Redirect preview</title>
+<style nonce="{nonce}">
+body {{ font-family: system-ui, sans-serif; max-width: 40em; margin: 4em auto; padding: 0 1em; }}
+code {{ word-break: break-all; }}
+</style>
+</head>
+<body>
+<h1>You are about to leave</h1>
+<p>This short link points to:</p>
+<p><code>{url}</code></p>
+<p><a href="/{id}" rel="noreferrer noopener">Continue</a></p>
+</body>
+</html>
+"#
+    )
+}
+
+pub fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#x27;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
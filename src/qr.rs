@@ -0,0 +1,20 @@
+use std::io::Cursor;
+
+use image::{ImageFormat, Luma};
+use qrcode::QrCode;
+
+/// Renders `data` as a PNG QR code with `module_size` pixels per module.
+pub fn render_png(data: &str, module_size: u32) -> Result<Vec<u8>, String> {
+    let code = QrCode::new(data.as_bytes()).map_err(|e| e.to_string())?;
+    let image = code
+        .render::<Luma<u8>>()
+        .module_dimensions(module_size, module_size)
+        .build();
+
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(|e| e.to_string())?;
+
+    Ok(png)
+}
@@ -0,0 +1,180 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use dashmap::DashMap;
+use moka::future::Cache;
+
+use crate::{auth::bearer_token, store::UrlStore, AppState, TinyUrlError};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Number of API keys whose limits are kept in memory.
+const API_KEY_CACHE_CAPACITY: u64 = 10_000;
+
+/// Who a request is counted against: its API key if it has a known one, its IP otherwise.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Client {
+    Ip(IpAddr),
+    Key(String),
+}
+
+/// Where a client stands in its current window.
+#[derive(Debug)]
+struct Quota {
+    limit: u64,
+    remaining: u64,
+    /// Seconds until the window resets.
+    reset: u64,
+    exceeded: bool,
+}
+
+impl Quota {
+    fn set_headers(&self, headers: &mut HeaderMap) {
+        headers.insert("x-ratelimit-limit", HeaderValue::from(self.limit));
+        headers.insert("x-ratelimit-remaining", HeaderValue::from(self.remaining));
+        headers.insert("x-ratelimit-reset", HeaderValue::from(self.reset));
+    }
+}
+
+/// Per-client request counter over a one minute window, with per-key limits from the
+/// `api_keys` table.
+#[derive(Debug)]
+pub struct RateLimiter {
+    default_limit: u64,
+    clients: DashMap<Client, (u64, Instant)>,
+    /// Limits of API keys looked up recently.
+    key_limits: Cache<String, u64>,
+}
+
+impl RateLimiter {
+    /// `default_limit` applies to clients without an API key. A limit of `0` disables rate
+    /// limiting.
+    pub fn new(default_limit: u64) -> Self {
+        Self {
+            default_limit,
+            clients: DashMap::new(),
+            key_limits: Cache::builder()
+                .max_capacity(API_KEY_CACHE_CAPACITY)
+                .time_to_live(WINDOW)
+                .build(),
+        }
+    }
+
+    /// The requests per minute allowed for API key `key`, or `None` if it is not a key.
+    ///
+    /// Known keys are cached for one window, so changes to `api_keys` apply within a minute.
+    /// Unknown ones are not, so that made-up tokens cannot crowd them out of the cache; they
+    /// are looked up only while `ip`, which their requests count against, is within its limit.
+    pub async fn key_limit<S: UrlStore>(
+        &self,
+        store: &S,
+        key: &str,
+        ip: IpAddr,
+    ) -> Result<Option<u64>, TinyUrlError> {
+        if let Some(limit) = self.key_limits.get(key).await {
+            return Ok(Some(limit));
+        }
+        if self.is_exceeded(&Client::Ip(ip), self.default_limit) {
+            return Ok(None);
+        }
+
+        let limit = store.api_key_limit(key).await?;
+        if let Some(limit) = limit {
+            self.key_limits.insert(key.to_string(), limit).await;
+        }
+
+        Ok(limit)
+    }
+
+    /// Whether the next request from `client` would go over `limit`, without counting one.
+    fn is_exceeded(&self, client: &Client, limit: u64) -> bool {
+        limit > 0
+            && self.clients.get(client).is_some_and(|entry| {
+                let (count, start) = *entry.value();
+                start.elapsed() < WINDOW && count >= limit
+            })
+    }
+
+    /// Counts a request from `client`, returning `None` if `limit` is `0`.
+    fn check(&self, client: Client, limit: u64) -> Option<Quota> {
+        if limit == 0 {
+            return None;
+        }
+
+        let now = Instant::now();
+        let mut entry = self.clients.entry(client).or_insert((0, now));
+        let (count, start) = entry.value_mut();
+
+        if now.duration_since(*start) >= WINDOW {
+            *count = 0;
+            *start = now;
+        }
+
+        *count += 1;
+
+        let reset = WINDOW.saturating_sub(now.duration_since(*start));
+        Some(Quota {
+            limit,
+            remaining: limit.saturating_sub(*count),
+            reset: reset.as_secs().max(1),
+            exceeded: *count > limit,
+        })
+    }
+
+    /// Drops clients whose window has already elapsed.
+    pub fn prune(&self) {
+        let now = Instant::now();
+        self.clients
+            .retain(|_, (_, start)| now.duration_since(*start) < WINDOW);
+    }
+}
+
+/// Middleware rejecting clients over their limit with `429 Too Many Requests`, and reporting
+/// the quota in `X-RateLimit-*` headers.
+pub async fn rate_limit<S: UrlStore>(
+    State(state): State<AppState<S>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Result<Response, TinyUrlError> {
+    let limiter = &state.rate_limiter;
+
+    let key = match bearer_token(req.headers()) {
+        Some(token) => limiter
+            .key_limit(&state.store, token, addr.ip())
+            .await?
+            .map(|limit| (Client::Key(token.to_string()), limit)),
+        None => None,
+    };
+    let (client, limit) = key.unwrap_or((Client::Ip(addr.ip()), limiter.default_limit));
+
+    let Some(quota) = limiter.check(client, limit) else {
+        return Ok(next.run(req).await);
+    };
+
+    let mut resp = if quota.exceeded {
+        TinyUrlError::RateLimited(quota.reset).into_response()
+    } else {
+        next.run(req).await
+    };
+    quota.set_headers(resp.headers_mut());
+
+    Ok(resp)
+}
+
+/// Periodically prunes stale clients so the map does not grow unbounded.
+pub async fn prune_periodically<S: UrlStore>(state: AppState<S>) {
+    let mut interval = tokio::time::interval(WINDOW);
+    loop {
+        interval.tick().await;
+        state.rate_limiter.prune();
+    }
+}
@@ -0,0 +1,48 @@
+//! XML sitemap of the public short links, so that search engines can discover them.
+
+use axum::{
+    body::Body,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
+
+use crate::{preview::escape_html, TinyUrlError};
+
+/// Most links a single sitemap may list, per the sitemaps protocol.
+pub const MAX_ENTRIES: u32 = 50_000;
+
+const HEADER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+"#;
+const FOOTER: &str = "</urlset>\n";
+
+/// Streams the sitemap listing `links`, codes with their creation times, under `base_url`.
+pub fn response(
+    base_url: &str,
+    links: BoxStream<'static, Result<(String, DateTime<Utc>), TinyUrlError>>,
+) -> Response {
+    let base_url = escape_html(base_url);
+    let entries = links.map(move |link| {
+        let (id, created_at) = link?;
+        Ok(format!(
+            "<url><loc>{}/{}</loc><lastmod>{}</lastmod></url>\n",
+            base_url,
+            escape_html(&id),
+            created_at.to_rfc3339_opts(SecondsFormat::Secs, true)
+        ))
+    });
+    let body = stream::once(async { Ok::<_, TinyUrlError>(HEADER.to_string()) })
+        .chain(entries)
+        .chain(stream::once(async { Ok(FOOTER.to_string()) }));
+
+    (
+        [(header::CONTENT_TYPE, "application/xml")],
+        Body::from_stream(body),
+    )
+        .into_response()
+}
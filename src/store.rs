@@ -0,0 +1,333 @@
+//! Persistence of short links behind the `UrlStore` trait, so that handlers do not
+//! depend on a particular database.
+
+/// Runs the `sqlx::query*` call `$query` as `$run`, e.g. `|query| query.fetch_one(&db)`.
+///
+/// Debug builds run it in a debug span named `$name` whose `sql` field holds the statement,
+/// with its bind parameters as placeholders, so that logs can be traced to the SQL behind
+/// them. Release builds skip the span.
+#[cfg(debug_assertions)]
+macro_rules! db_query {
+    ($name:literal, $query:expr, |$q:ident| $run:expr) => {{
+        let $q = $query;
+        let span = tracing::debug_span!($name, sql = sqlx::Execute::sql(&$q).trim());
+        tracing::Instrument::instrument($run, span)
+    }};
+}
+
+#[cfg(not(debug_assertions))]
+macro_rules! db_query {
+    ($name:literal, $query:expr, |$q:ident| $run:expr) => {{
+        let $q = $query;
+        $run
+    }};
+}
+
+#[cfg(any(test, feature = "memory-store"))]
+mod memory;
+mod postgres;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use futures::{stream::BoxStream, StreamExt};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+#[cfg(any(test, feature = "memory-store"))]
+pub use memory::InMemoryStore;
+pub use postgres::PostgresStore;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
+/// Delay before repeating a store call that failed on a dropped connection.
+const TRANSIENT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+use crate::{
+    AuditAction, AuditEntry, Click, ClickBucket, ClickInfo, Cursor, DomainStats, ExportRecord,
+    GlobalStats, Granularity, RedirectTarget, SearchHit, ShortenRequest, TinyUrlError,
+    UpdateRequest, UrlRecord, Webhook, WebhookEvent, WebhookRequest,
+};
+
+/// Storage backend of `AppState`.
+///
+/// Inputs are already validated and normalized; implementations only persist them. Links are
+/// live until they are soft-deleted or expire, and soft-deleted links are kept as tombstones
+/// until purged.
+#[async_trait]
+pub trait UrlStore: Clone + Send + Sync + 'static {
+    /// Stores `url` under `id` with the options of `req`, returning the code it ends up under
+    /// and whether it was created: `id`, or the existing code if `url` is already shortened.
+    ///
+    /// Fails with `CodeAlreadyTaken` if `id` is in use, tombstones included.
+    async fn shorten(
+        &self,
+        id: &str,
+        url: &str,
+        req: &ShortenRequest,
+    ) -> Result<(String, bool), TinyUrlError>;
+
+    /// Reserves `id` without a destination until `until`, failing with `CodeAlreadyTaken` if
+    /// it is in use. Reserved codes do not resolve until `update_url` activates them.
+    async fn reserve(&self, id: &str, until: DateTime<Utc>) -> Result<(), TinyUrlError>;
+
+    /// Permanently removes links that expired or used up their clicks, returning how many.
+    async fn delete_expired(&self) -> Result<u64, TinyUrlError>;
+
+    /// Removes reservations that lapsed without being activated, returning how many.
+    async fn release_expired_reservations(&self) -> Result<u64, TinyUrlError>;
+
+    /// Resolves a live link and counts a click, retiring it when it reaches its click limit.
+    ///
+    /// Fails with `LinkExpired` for links that used up their clicks. Without counting a
+    /// click, it fails with `RefererNotAllowed` if the link restricts its referers and
+    /// `referer`, an origin, is not among them, and with `PasswordRequired` for
+    /// password-protected links unless `unlocked`.
+    async fn get_url_by_id(
+        &self,
+        id: &str,
+        referer: Option<&str>,
+        unlocked: bool,
+    ) -> Result<RedirectTarget, TinyUrlError>;
+
+    /// bcrypt hash of the password protecting a live link, if it has one.
+    async fn password_hash(&self, id: &str) -> Result<Option<String>, TinyUrlError>;
+
+    /// Resolves a live link without counting a click.
+    ///
+    /// Fails with `RefererNotAllowed` if the link may not be followed from the `referer`
+    /// origin, and with `PasswordRequired` for password-protected links.
+    async fn peek_url(&self, id: &str, referer: Option<&str>) -> Result<String, TinyUrlError>;
+
+    /// The code of the oldest plain link pointing to `url`, of the kind `urls_url_live_key`
+    /// covers, failing with `IdNotFound` if there is none.
+    async fn find_by_url(&self, url: &str) -> Result<String, TinyUrlError>;
+
+    /// Counts a click on a link that was resolved from the cache.
+    async fn count_click(&self, id: &str) -> Result<(), TinyUrlError>;
+
+    async fn record_click(&self, id: &str, info: &ClickInfo) -> Result<(), TinyUrlError>;
+
+    /// Clicks recorded for `id` in `[from, to)`, newest first.
+    async fn list_clicks(
+        &self,
+        id: &str,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<Vec<Click>, TinyUrlError>;
+
+    /// Clicks recorded for `id` in `[from, to)`, counted per `granularity` bucket in UTC,
+    /// oldest first. Buckets without clicks are left out.
+    async fn click_timeseries(
+        &self,
+        id: &str,
+        granularity: Granularity,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<ClickBucket>, TinyUrlError>;
+
+    /// The request fingerprint and short code stored under `key`, unless older than `ttl`.
+    /// The code is `None` while the request that claimed the key is still running.
+    async fn get_idempotency_key(
+        &self,
+        key: Uuid,
+        ttl: Duration,
+    ) -> Result<Option<(String, Option<String>)>, TinyUrlError>;
+
+    /// Claims `key` for the request fingerprinted as `request`, returning whether it was free.
+    /// Only an entry older than `ttl`, which lookups already ignore, is ever replaced.
+    async fn claim_idempotency_key(
+        &self,
+        key: Uuid,
+        request: &str,
+        ttl: Duration,
+    ) -> Result<bool, TinyUrlError>;
+
+    /// Remembers that the request holding the claim on `key` produced `id`.
+    async fn save_idempotency_key(&self, key: Uuid, id: &str) -> Result<(), TinyUrlError>;
+
+    /// Gives up the claim on `key` of a request that failed, so that a retry runs it again.
+    async fn release_idempotency_key(&self, key: Uuid) -> Result<(), TinyUrlError>;
+
+    /// Forgets idempotency keys older than `ttl`.
+    async fn purge_idempotency_keys(&self, ttl: Duration) -> Result<(), TinyUrlError>;
+
+    /// Applies the changes of `req` to a non-deleted link, with `url` already normalized.
+    /// Empty `notes` remove the notes.
+    ///
+    /// A new `url` retargets the link or activates a pending reservation, failing with
+    /// `UrlAlreadyExists` if another live link already points to it.
+    async fn update_url(
+        &self,
+        id: &str,
+        url: Option<&str>,
+        req: &UpdateRequest,
+    ) -> Result<(), TinyUrlError>;
+
+    /// Soft-deletes a link, leaving a tombstone.
+    async fn delete_url(&self, id: &str) -> Result<(), TinyUrlError>;
+
+    /// Clears the tombstone of a soft-deleted link, failing with `IdNotFound` if `id` is
+    /// unknown or not deleted and with `UrlAlreadyExists` if another live link took its URL.
+    async fn restore_url(&self, id: &str) -> Result<(), TinyUrlError>;
+
+    /// Adds `delta` to the click count of a link, or zeroes it if `reset`, returning the new
+    /// count. Counts are kept from dropping below zero. Deleted links are `IdNotFound`.
+    async fn adjust_clicks(&self, id: &str, delta: i64, reset: bool) -> Result<i64, TinyUrlError>;
+
+    /// Permanently removes links soft-deleted more than `older_than` ago.
+    async fn purge_deleted(&self, older_than: Duration) -> Result<u64, TinyUrlError>;
+
+    /// The record of a code that is neither deleted nor reserved.
+    ///
+    /// Fails with `PasswordRequired` for password-protected links unless `unlocked`, since
+    /// the record gives their URL away.
+    async fn get_stats(&self, id: &str, unlocked: bool) -> Result<UrlRecord, TinyUrlError>;
+
+    /// Totals over all live links and the ten most clicked ones.
+    async fn global_stats(&self) -> Result<GlobalStats, TinyUrlError>;
+
+    /// Live links and their clicks per host, in no particular order.
+    async fn count_by_host(&self) -> Result<Vec<DomainStats>, TinyUrlError>;
+
+    /// Up to `limit` live links whose URL contains `keyword`, ignoring case, newest first.
+    async fn search_urls(&self, keyword: &str, limit: u32) -> Result<Vec<SearchHit>, TinyUrlError>;
+
+    /// Up to `limit` non-deleted links following `after`, optionally only those tagged `tag`,
+    /// newest first.
+    async fn list_urls(
+        &self,
+        after: Option<&Cursor>,
+        limit: u32,
+        tag: Option<&str>,
+    ) -> Result<Vec<UrlRecord>, TinyUrlError>;
+
+    /// A page of tombstones, most recently deleted first, and their total count.
+    async fn list_deleted_urls(
+        &self,
+        page: u32,
+        per_page: u32,
+    ) -> Result<(Vec<UrlRecord>, i64), TinyUrlError>;
+
+    /// Appends an entry to the audit log, which is never pruned.
+    async fn record_audit(
+        &self,
+        action: AuditAction,
+        id: &str,
+        actor: &str,
+    ) -> Result<(), TinyUrlError>;
+
+    /// A page of audit entries, optionally only for `id`, in `[from, to)`, newest first, and
+    /// their total count.
+    async fn list_audit(
+        &self,
+        id: Option<&str>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        page: u32,
+        per_page: u32,
+    ) -> Result<(Vec<AuditEntry>, i64), TinyUrlError>;
+
+    /// Inserts `(id, url)` links in one transaction, skipping ones whose code or URL is
+    /// already stored, and returns the codes that were inserted.
+    async fn import(&self, links: &[(String, String)]) -> Result<Vec<String>, TinyUrlError>;
+
+    /// Inserts `(id, url)` links all at once, failing without inserting any if a code or URL
+    /// is already stored, and returns how many were inserted.
+    async fn bulk_insert(&self, links: &[(String, String)]) -> Result<u64, TinyUrlError>;
+
+    /// Every link except pending reservations, tombstones included, oldest first, streamed
+    /// rather than loaded at once.
+    fn export(&self) -> BoxStream<'static, Result<ExportRecord, TinyUrlError>>;
+
+    /// A page of the codes of live links that are neither private nor password-protected,
+    /// with their creation times, oldest first and streamed like `export`.
+    fn sitemap(
+        &self,
+        page: u32,
+        per_page: u32,
+    ) -> BoxStream<'static, Result<(String, DateTime<Utc>), TinyUrlError>>;
+
+    /// Registers the webhook of `req` with `target_url` already normalized.
+    async fn create_webhook(
+        &self,
+        target_url: &str,
+        req: &WebhookRequest,
+    ) -> Result<Webhook, TinyUrlError>;
+
+    /// Every webhook subscribed to `event`, for any code.
+    async fn list_webhooks(&self, event: WebhookEvent) -> Result<Vec<Webhook>, TinyUrlError>;
+
+    /// The requests per minute allowed for API key `key`, or `None` if it is not a key.
+    async fn api_key_limit(&self, key: &str) -> Result<Option<u64>, TinyUrlError>;
+
+    /// Checks that the backend is reachable, failing with `HealthCheckFailed`.
+    async fn ping(&self) -> Result<(), TinyUrlError>;
+}
+
+/// Runs `f`, repeating it once after `TRANSIENT_RETRY_DELAY` if it fails on a connection-level
+/// database error, so that a database restart does not fail requests while the pool reconnects.
+pub async fn retry_on_transient<T, F, Fut>(mut f: F) -> Result<T, TinyUrlError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, TinyUrlError>>,
+{
+    match f().await {
+        Err(TinyUrlError::DatabaseError(e)) if is_connection_error(&e) => {
+            tracing::warn!("Retrying after transient database error: {}", e);
+            tokio::time::sleep(TRANSIENT_RETRY_DELAY).await;
+            f().await
+        }
+        res => res,
+    }
+}
+
+/// Awaits `f`, logging a warning naming the query `name` if it takes longer than `threshold`.
+pub async fn timed_query<T, F>(name: &str, threshold: Duration, f: F) -> T
+where
+    F: Future<Output = T>,
+{
+    let start = Instant::now();
+    let res = f.await;
+
+    let elapsed = start.elapsed();
+    if elapsed > threshold {
+        tracing::warn!("Slow query {} took {:?}", name, elapsed);
+    }
+
+    res
+}
+
+/// Sends `rows` to `tx` until they run out or the receiving client went away.
+async fn forward<T>(
+    mut rows: BoxStream<'_, Result<T, sqlx::Error>>,
+    tx: mpsc::Sender<Result<T, TinyUrlError>>,
+) {
+    while let Some(row) = rows.next().await {
+        if tx.send(row.map_err(TinyUrlError::from)).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// The rows sent by `forward`, as a stream that does not borrow the pool.
+fn received<T: Send + 'static>(
+    rx: mpsc::Receiver<Result<T, TinyUrlError>>,
+) -> BoxStream<'static, Result<T, TinyUrlError>> {
+    futures::stream::unfold(
+        rx,
+        |mut rx| async move { rx.recv().await.map(|row| (row, rx)) },
+    )
+    .boxed()
+}
+
+/// Whether `e` means the database could not be reached, rather than that a query failed.
+fn is_connection_error(e: &sqlx::Error) -> bool {
+    matches!(e, sqlx::Error::PoolClosed | sqlx::Error::Io(_))
+}
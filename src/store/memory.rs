@@ -0,0 +1,1446 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::async_trait;
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use futures::{
+    stream::{self, BoxStream},
+    StreamExt,
+};
+use tokio::sync::RwLock;
+use url::Url;
+use uuid::Uuid;
+
+use super::UrlStore;
+use crate::{
+    allows_referer, AuditAction, AuditEntry, Click, ClickBucket, ClickInfo, Cursor, DomainStats,
+    ExportRecord, GlobalStats, Granularity, RedirectTarget, RedirectType, SearchHit,
+    ShortenRequest, TinyUrlError, UpdateRequest, UrlRecord, Webhook, WebhookEvent, WebhookRequest,
+};
+
+/// Store keeping everything in process memory, for tests and benchmarks that need no
+/// database.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryStore {
+    urls: Arc<RwLock<HashMap<String, Entry>>>,
+    clicks: Arc<RwLock<Vec<(String, Click)>>>,
+    idempotency_keys: Arc<RwLock<HashMap<Uuid, IdempotencyEntry>>>,
+    audit_log: Arc<RwLock<Vec<AuditEntry>>>,
+    api_keys: Arc<RwLock<HashMap<String, u64>>>,
+    webhooks: Arc<RwLock<Vec<Webhook>>>,
+}
+
+#[derive(Debug)]
+struct IdempotencyEntry {
+    request: String,
+    id: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+/// A stored link: the record reported by the API plus the options only redirects use.
+#[derive(Debug)]
+struct Entry {
+    record: UrlRecord,
+    expires_at: Option<DateTime<Utc>>,
+    redirect_type: RedirectType,
+    max_clicks: Option<i32>,
+    reserved_until: Option<DateTime<Utc>>,
+    allowed_referers: Vec<String>,
+    password_hash: Option<String>,
+    private: bool,
+}
+
+impl Entry {
+    /// A link with nothing but a code and a URL, as imported.
+    fn imported(id: &str, url: &str) -> Self {
+        Entry {
+            record: UrlRecord {
+                id: id.to_string(),
+                url: url.to_string(),
+                clicks: 0,
+                created_at: Utc::now(),
+                deleted_at: None,
+                tags: Vec::new(),
+                notes: None,
+            },
+            expires_at: None,
+            redirect_type: RedirectType::default(),
+            max_clicks: None,
+            reserved_until: None,
+            allowed_referers: Vec::new(),
+            password_hash: None,
+            private: false,
+        }
+    }
+
+    fn is_live(&self) -> bool {
+        self.is_active() && self.expires_at.is_none_or(|at| at > Utc::now())
+    }
+
+    /// Not deleted and not a pending reservation.
+    fn is_active(&self) -> bool {
+        self.record.deleted_at.is_none() && self.reserved_until.is_none()
+    }
+
+    fn is_used_up(&self) -> bool {
+        self.max_clicks
+            .is_some_and(|max| self.record.clicks >= i64::from(max))
+    }
+
+    /// What `urls_url_live_key` keeps unique, for the plain links it covers: those with
+    /// none of the options that make a link expire, run out or turn away visitors.
+    fn url_key(&self) -> Option<(&str, RedirectType)> {
+        let plain = self.expires_at.is_none()
+            && self.max_clicks.is_none()
+            && self.password_hash.is_none()
+            && self.allowed_referers.is_empty()
+            && !self.private;
+        plain.then_some((&self.record.url, self.redirect_type))
+    }
+}
+
+/// Whether an active link other than `id` already holds `key`.
+fn url_key_taken(
+    urls: &HashMap<String, Entry>,
+    id: &str,
+    key: Option<(&str, RedirectType)>,
+) -> bool {
+    key.is_some()
+        && urls
+            .values()
+            .any(|e| e.record.id != id && e.is_active() && e.url_key() == key)
+}
+
+impl InMemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds API key `key` allowing `limit` requests per minute.
+    pub async fn add_api_key(&self, key: &str, limit: u64) {
+        self.api_keys.write().await.insert(key.to_string(), limit);
+    }
+}
+
+/// Sorts `records` by `key`, newest first, and cuts out one page.
+fn paginate<K: Ord>(
+    mut records: Vec<UrlRecord>,
+    page: u32,
+    per_page: u32,
+    key: impl Fn(&UrlRecord) -> K,
+) -> (Vec<UrlRecord>, i64) {
+    records.sort_by_key(|r| std::cmp::Reverse(key(r)));
+
+    let total = records.len() as i64;
+    let offset = page.saturating_sub(1) as usize * per_page as usize;
+    let page = records
+        .into_iter()
+        .skip(offset)
+        .take(per_page as usize)
+        .collect();
+
+    (page, total)
+}
+
+#[async_trait]
+impl UrlStore for InMemoryStore {
+    async fn shorten(
+        &self,
+        id: &str,
+        url: &str,
+        req: &ShortenRequest,
+    ) -> Result<(String, bool), TinyUrlError> {
+        let mut urls = self.urls.write().await;
+
+        let key = Some((url, req.redirect_type)).filter(|_| req.reusable());
+        if let Some(existing) = urls
+            .values()
+            .find(|e| key.is_some() && e.is_active() && e.url_key() == key)
+        {
+            return Ok((existing.record.id.clone(), false));
+        }
+
+        if urls.contains_key(id) {
+            return Err(TinyUrlError::CodeAlreadyTaken(id.to_string()));
+        }
+
+        let now = Utc::now();
+        let expires_at = req
+            .ttl_seconds_i64()
+            .and_then(TimeDelta::try_seconds)
+            .and_then(|ttl| now.checked_add_signed(ttl));
+
+        let entry = Entry {
+            record: UrlRecord {
+                id: id.to_string(),
+                url: url.to_string(),
+                clicks: 0,
+                created_at: now,
+                deleted_at: None,
+                tags: req.tags.clone().unwrap_or_default(),
+                notes: req.notes.clone().filter(|notes| !notes.is_empty()),
+            },
+            expires_at,
+            redirect_type: req.redirect_type,
+            max_clicks: req.max_clicks_i32(),
+            reserved_until: None,
+            allowed_referers: req.referer_origins(),
+            password_hash: req.password_hash.clone(),
+            private: req.private,
+        };
+        urls.insert(id.to_string(), entry);
+
+        Ok((id.to_string(), true))
+    }
+
+    async fn reserve(&self, id: &str, until: DateTime<Utc>) -> Result<(), TinyUrlError> {
+        let mut urls = self.urls.write().await;
+        if urls.contains_key(id) {
+            return Err(TinyUrlError::CodeAlreadyTaken(id.to_string()));
+        }
+
+        urls.insert(
+            id.to_string(),
+            Entry {
+                record: UrlRecord {
+                    id: id.to_string(),
+                    url: String::new(),
+                    clicks: 0,
+                    created_at: Utc::now(),
+                    deleted_at: None,
+                    tags: Vec::new(),
+                    notes: None,
+                },
+                expires_at: None,
+                redirect_type: RedirectType::default(),
+                max_clicks: None,
+                reserved_until: Some(until),
+                allowed_referers: Vec::new(),
+                password_hash: None,
+                private: false,
+            },
+        );
+
+        Ok(())
+    }
+
+    async fn delete_expired(&self) -> Result<u64, TinyUrlError> {
+        let now = Utc::now();
+        let mut urls = self.urls.write().await;
+        let before = urls.len();
+        urls.retain(|_, e| e.expires_at.is_none_or(|at| at >= now) && !e.is_used_up());
+        let deleted = before - urls.len();
+
+        self.clicks
+            .write()
+            .await
+            .retain(|(url_id, _)| urls.contains_key(url_id));
+        self.idempotency_keys
+            .write()
+            .await
+            .retain(|_, e| e.id.as_ref().is_none_or(|id| urls.contains_key(id)));
+
+        Ok(deleted as u64)
+    }
+
+    async fn release_expired_reservations(&self) -> Result<u64, TinyUrlError> {
+        let now = Utc::now();
+        let mut urls = self.urls.write().await;
+        let before = urls.len();
+        urls.retain(|_, e| e.reserved_until.is_none_or(|until| until >= now));
+
+        Ok((before - urls.len()) as u64)
+    }
+
+    async fn get_url_by_id(
+        &self,
+        id: &str,
+        referer: Option<&str>,
+        unlocked: bool,
+    ) -> Result<RedirectTarget, TinyUrlError> {
+        let mut urls = self.urls.write().await;
+
+        // checked before liveness, for links restored or adjusted past their last click
+        let entry = match urls.get_mut(id) {
+            Some(entry) if entry.is_used_up() => {
+                return Err(TinyUrlError::LinkExpired(id.to_string()))
+            }
+            Some(entry) if entry.is_live() && !allows_referer(&entry.allowed_referers, referer) => {
+                return Err(TinyUrlError::RefererNotAllowed(id.to_string()))
+            }
+            Some(entry) if entry.is_live() && entry.password_hash.is_some() && !unlocked => {
+                return Err(TinyUrlError::PasswordRequired(id.to_string()))
+            }
+            Some(entry) if entry.is_live() => entry,
+            _ => return Err(TinyUrlError::IdNotFound(id.to_string())),
+        };
+
+        entry.record.clicks += 1;
+        if entry.is_used_up() {
+            entry.record.deleted_at = Some(Utc::now());
+        }
+
+        Ok(RedirectTarget {
+            url: entry.record.url.clone(),
+            redirect_type: entry.redirect_type,
+            expires_at: entry.expires_at,
+            max_clicks: entry.max_clicks,
+            created_at: Some(entry.record.created_at),
+            allowed_referers: entry.allowed_referers.clone(),
+            password_protected: entry.password_hash.is_some(),
+        })
+    }
+
+    async fn password_hash(&self, id: &str) -> Result<Option<String>, TinyUrlError> {
+        self.urls
+            .read()
+            .await
+            .get(id)
+            .filter(|e| e.is_live())
+            .map(|e| e.password_hash.clone())
+            .ok_or(TinyUrlError::IdNotFound(id.to_string()))
+    }
+
+    async fn peek_url(&self, id: &str, referer: Option<&str>) -> Result<String, TinyUrlError> {
+        match self.urls.read().await.get(id).filter(|e| e.is_live()) {
+            Some(e) if !allows_referer(&e.allowed_referers, referer) => {
+                Err(TinyUrlError::RefererNotAllowed(id.to_string()))
+            }
+            Some(e) if e.password_hash.is_some() => {
+                Err(TinyUrlError::PasswordRequired(id.to_string()))
+            }
+            Some(e) => Ok(e.record.url.clone()),
+            None => Err(TinyUrlError::IdNotFound(id.to_string())),
+        }
+    }
+
+    async fn find_by_url(&self, url: &str) -> Result<String, TinyUrlError> {
+        self.urls
+            .read()
+            .await
+            .values()
+            .filter(|e| e.is_active() && e.url_key().is_some_and(|(key, _)| key == url))
+            .min_by_key(|e| e.record.created_at)
+            .map(|e| e.record.id.clone())
+            .ok_or(TinyUrlError::IdNotFound(url.to_string()))
+    }
+
+    async fn count_click(&self, id: &str) -> Result<(), TinyUrlError> {
+        if let Some(entry) = self.urls.write().await.get_mut(id) {
+            entry.record.clicks += 1;
+        }
+
+        Ok(())
+    }
+
+    async fn record_click(&self, id: &str, info: &ClickInfo) -> Result<(), TinyUrlError> {
+        let click = Click {
+            clicked_at: Utc::now(),
+            ip_address: Some(info.ip.to_string()),
+            user_agent: info.user_agent.clone(),
+            referer: info.referer.clone(),
+        };
+        self.clicks.write().await.push((id.to_string(), click));
+
+        Ok(())
+    }
+
+    async fn list_clicks(
+        &self,
+        id: &str,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<Vec<Click>, TinyUrlError> {
+        let clicks = self.clicks.read().await;
+
+        Ok(clicks
+            .iter()
+            .rev()
+            .filter(|(url_id, _)| url_id == id)
+            .map(|(_, click)| click)
+            .filter(|c| from.is_none_or(|from| c.clicked_at >= from))
+            .filter(|c| to.is_none_or(|to| c.clicked_at < to))
+            .cloned()
+            .collect())
+    }
+
+    async fn click_timeseries(
+        &self,
+        id: &str,
+        granularity: Granularity,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<ClickBucket>, TinyUrlError> {
+        let mut counts = BTreeMap::new();
+        for (_, click) in self
+            .clicks
+            .read()
+            .await
+            .iter()
+            .filter(|(url_id, _)| url_id == id)
+        {
+            if click.clicked_at >= from && click.clicked_at < to {
+                let bucket = click
+                    .clicked_at
+                    .duration_trunc(granularity.width())
+                    .unwrap_or(click.clicked_at);
+                *counts.entry(bucket).or_insert(0) += 1;
+            }
+        }
+
+        Ok(counts
+            .into_iter()
+            .map(|(bucket, clicks)| ClickBucket { bucket, clicks })
+            .collect())
+    }
+
+    async fn get_idempotency_key(
+        &self,
+        key: Uuid,
+        ttl: Duration,
+    ) -> Result<Option<(String, Option<String>)>, TinyUrlError> {
+        let ttl = TimeDelta::from_std(ttl).unwrap_or(TimeDelta::MAX);
+
+        Ok(self
+            .idempotency_keys
+            .read()
+            .await
+            .get(&key)
+            .filter(|e| Utc::now() - e.created_at < ttl)
+            .map(|e| (e.request.clone(), e.id.clone())))
+    }
+
+    async fn claim_idempotency_key(
+        &self,
+        key: Uuid,
+        request: &str,
+        ttl: Duration,
+    ) -> Result<bool, TinyUrlError> {
+        let ttl = TimeDelta::from_std(ttl).unwrap_or(TimeDelta::MAX);
+        let now = Utc::now();
+        let mut keys = self.idempotency_keys.write().await;
+
+        if keys.get(&key).is_some_and(|e| now - e.created_at < ttl) {
+            return Ok(false);
+        }
+        keys.insert(
+            key,
+            IdempotencyEntry {
+                request: request.to_string(),
+                id: None,
+                created_at: now,
+            },
+        );
+
+        Ok(true)
+    }
+
+    async fn save_idempotency_key(&self, key: Uuid, id: &str) -> Result<(), TinyUrlError> {
+        if let Some(entry) = self.idempotency_keys.write().await.get_mut(&key) {
+            entry.id = Some(id.to_string());
+        }
+
+        Ok(())
+    }
+
+    async fn release_idempotency_key(&self, key: Uuid) -> Result<(), TinyUrlError> {
+        let mut keys = self.idempotency_keys.write().await;
+        if keys.get(&key).is_some_and(|e| e.id.is_none()) {
+            keys.remove(&key);
+        }
+
+        Ok(())
+    }
+
+    async fn purge_idempotency_keys(&self, ttl: Duration) -> Result<(), TinyUrlError> {
+        let ttl = TimeDelta::from_std(ttl).unwrap_or(TimeDelta::MAX);
+        let now = Utc::now();
+        self.idempotency_keys
+            .write()
+            .await
+            .retain(|_, e| now - e.created_at < ttl);
+
+        Ok(())
+    }
+
+    async fn update_url(
+        &self,
+        id: &str,
+        url: Option<&str>,
+        req: &UpdateRequest,
+    ) -> Result<(), TinyUrlError> {
+        let mut urls = self.urls.write().await;
+
+        if let (Some(url), Some(entry)) = (url, urls.get(id)) {
+            if url_key_taken(&urls, id, entry.url_key().map(|(_, rt)| (url, rt))) {
+                return Err(TinyUrlError::UrlAlreadyExists(url.to_string()));
+            }
+        }
+
+        let now = Utc::now();
+        match urls.get_mut(id).filter(|e| {
+            e.record.deleted_at.is_none() && e.reserved_until.is_none_or(|until| until > now)
+        }) {
+            Some(entry) => {
+                if let Some(url) = url {
+                    entry.record.url = url.to_string();
+                    entry.reserved_until = None;
+                }
+                if let Some(tags) = &req.tags {
+                    entry.record.tags = tags.clone();
+                }
+                if let Some(notes) = &req.notes {
+                    entry.record.notes = Some(notes.clone()).filter(|notes| !notes.is_empty());
+                }
+                Ok(())
+            }
+            None => Err(TinyUrlError::IdNotFound(id.to_string())),
+        }
+    }
+
+    async fn delete_url(&self, id: &str) -> Result<(), TinyUrlError> {
+        match self
+            .urls
+            .write()
+            .await
+            .get_mut(id)
+            .filter(|e| e.record.deleted_at.is_none())
+        {
+            Some(entry) => {
+                entry.record.deleted_at = Some(Utc::now());
+                Ok(())
+            }
+            None => Err(TinyUrlError::IdNotFound(id.to_string())),
+        }
+    }
+
+    async fn restore_url(&self, id: &str) -> Result<(), TinyUrlError> {
+        let mut urls = self.urls.write().await;
+
+        let entry = match urls.get(id).filter(|e| e.record.deleted_at.is_some()) {
+            Some(entry) => entry,
+            None => return Err(TinyUrlError::IdNotFound(id.to_string())),
+        };
+        if url_key_taken(&urls, id, entry.url_key()) {
+            return Err(TinyUrlError::UrlAlreadyExists(entry.record.url.clone()));
+        }
+
+        if let Some(entry) = urls.get_mut(id) {
+            entry.record.deleted_at = None;
+        }
+        Ok(())
+    }
+
+    async fn adjust_clicks(&self, id: &str, delta: i64, reset: bool) -> Result<i64, TinyUrlError> {
+        let mut urls = self.urls.write().await;
+        let entry = urls
+            .get_mut(id)
+            .filter(|e| e.record.deleted_at.is_none())
+            .ok_or(TinyUrlError::IdNotFound(id.to_string()))?;
+
+        entry.record.clicks = if reset {
+            0
+        } else {
+            entry.record.clicks.saturating_add(delta).max(0)
+        };
+        Ok(entry.record.clicks)
+    }
+
+    async fn purge_deleted(&self, older_than: Duration) -> Result<u64, TinyUrlError> {
+        let cutoff = TimeDelta::from_std(older_than)
+            .ok()
+            .and_then(|age| Utc::now().checked_sub_signed(age));
+        let Some(cutoff) = cutoff else {
+            return Ok(0);
+        };
+
+        let mut urls = self.urls.write().await;
+        let before = urls.len();
+        urls.retain(|_, e| e.record.deleted_at.is_none_or(|at| at >= cutoff));
+        let purged = before - urls.len();
+
+        self.clicks
+            .write()
+            .await
+            .retain(|(url_id, _)| urls.contains_key(url_id));
+        self.idempotency_keys
+            .write()
+            .await
+            .retain(|_, e| e.id.as_ref().is_none_or(|id| urls.contains_key(id)));
+
+        Ok(purged as u64)
+    }
+
+    async fn get_stats(&self, id: &str, unlocked: bool) -> Result<UrlRecord, TinyUrlError> {
+        match self.urls.read().await.get(id).filter(|e| e.is_active()) {
+            Some(e) if e.password_hash.is_some() && !unlocked => {
+                Err(TinyUrlError::PasswordRequired(id.to_string()))
+            }
+            Some(e) => Ok(e.record.clone()),
+            None => Err(TinyUrlError::IdNotFound(id.to_string())),
+        }
+    }
+
+    async fn global_stats(&self) -> Result<GlobalStats, TinyUrlError> {
+        let now = Utc::now();
+        let day = TimeDelta::days(1);
+
+        let urls = self.urls.read().await;
+        let mut live: Vec<_> = urls.values().filter(|e| e.is_live()).collect();
+
+        let stats = GlobalStats {
+            total_urls: live.len() as i64,
+            total_clicks: live.iter().map(|e| e.record.clicks).sum(),
+            urls_created_last_24h: live
+                .iter()
+                .filter(|e| e.record.created_at > now - day)
+                .count() as i64,
+            urls_expiring_next_24h: live
+                .iter()
+                .filter(|e| e.expires_at.is_some_and(|at| at <= now + day))
+                .count() as i64,
+            top_10_urls: Vec::new(),
+        };
+
+        live.sort_by_key(|e| std::cmp::Reverse((e.record.clicks, e.record.created_at)));
+        Ok(GlobalStats {
+            top_10_urls: live.iter().take(10).map(|e| e.record.clone()).collect(),
+            ..stats
+        })
+    }
+
+    async fn count_by_host(&self) -> Result<Vec<DomainStats>, TinyUrlError> {
+        let mut hosts: HashMap<String, DomainStats> = HashMap::new();
+        for entry in self.urls.read().await.values().filter(|e| e.is_active()) {
+            let url = Url::parse(&entry.record.url).expect("stored urls are valid");
+            let host = url.host_str().unwrap_or_default().to_string();
+
+            let stats = hosts.entry(host.clone()).or_insert(DomainStats {
+                domain: host,
+                urls: 0,
+                clicks: 0,
+            });
+            stats.urls += 1;
+            stats.clicks += entry.record.clicks;
+        }
+
+        Ok(hosts.into_values().collect())
+    }
+
+    async fn search_urls(&self, keyword: &str, limit: u32) -> Result<Vec<SearchHit>, TinyUrlError> {
+        let keyword = keyword.to_lowercase();
+        let urls = self.urls.read().await;
+        let mut matches: Vec<&UrlRecord> = urls
+            .values()
+            .filter(|e| e.is_active() && e.record.url.to_lowercase().contains(&keyword))
+            .map(|e| &e.record)
+            .collect();
+        matches.sort_by(|a, b| (b.created_at, &b.id).cmp(&(a.created_at, &a.id)));
+
+        Ok(matches
+            .into_iter()
+            .take(limit as usize)
+            .map(|r| SearchHit {
+                id: r.id.clone(),
+                url: r.url.clone(),
+                clicks: r.clicks,
+            })
+            .collect())
+    }
+
+    async fn list_urls(
+        &self,
+        after: Option<&Cursor>,
+        limit: u32,
+        tag: Option<&str>,
+    ) -> Result<Vec<UrlRecord>, TinyUrlError> {
+        let position = |r: &UrlRecord| (r.created_at, r.id.clone());
+        let after = after.map(|cursor| (cursor.created_at, cursor.id.clone()));
+
+        let urls = self.urls.read().await;
+        let mut live: Vec<_> = urls
+            .values()
+            .filter(|e| e.is_active())
+            .filter(|e| tag.is_none_or(|tag| e.record.tags.iter().any(|t| t == tag)))
+            .filter(|e| {
+                after
+                    .as_ref()
+                    .is_none_or(|after| position(&e.record) < *after)
+            })
+            .map(|e| e.record.clone())
+            .collect();
+        live.sort_by_key(|r| std::cmp::Reverse(position(r)));
+        live.truncate(limit as usize);
+
+        Ok(live)
+    }
+
+    async fn list_deleted_urls(
+        &self,
+        page: u32,
+        per_page: u32,
+    ) -> Result<(Vec<UrlRecord>, i64), TinyUrlError> {
+        let urls = self.urls.read().await;
+        let deleted = urls
+            .values()
+            .map(|e| &e.record)
+            .filter(|r| r.deleted_at.is_some())
+            .cloned()
+            .collect();
+
+        Ok(paginate(deleted, page, per_page, |r| r.deleted_at))
+    }
+
+    async fn record_audit(
+        &self,
+        action: AuditAction,
+        id: &str,
+        actor: &str,
+    ) -> Result<(), TinyUrlError> {
+        let mut log = self.audit_log.write().await;
+        let entry = AuditEntry {
+            id: log.len() as i64 + 1,
+            action,
+            url_id: id.to_string(),
+            actor: actor.to_string(),
+            timestamp: Utc::now(),
+        };
+        log.push(entry);
+
+        Ok(())
+    }
+
+    async fn list_audit(
+        &self,
+        id: Option<&str>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        page: u32,
+        per_page: u32,
+    ) -> Result<(Vec<AuditEntry>, i64), TinyUrlError> {
+        let log = self.audit_log.read().await;
+        let matching: Vec<&AuditEntry> = log
+            .iter()
+            .rev()
+            .filter(|e| id.is_none_or(|id| e.url_id == id))
+            .filter(|e| from.is_none_or(|from| e.timestamp >= from))
+            .filter(|e| to.is_none_or(|to| e.timestamp < to))
+            .collect();
+
+        let total = matching.len() as i64;
+        let offset = page.saturating_sub(1) as usize * per_page as usize;
+        let page = matching
+            .into_iter()
+            .skip(offset)
+            .take(per_page as usize)
+            .cloned()
+            .collect();
+
+        Ok((page, total))
+    }
+
+    async fn import(&self, links: &[(String, String)]) -> Result<Vec<String>, TinyUrlError> {
+        let mut urls = self.urls.write().await;
+        let mut imported = Vec::new();
+
+        for (id, url) in links {
+            let entry = Entry::imported(id, url);
+            if urls.contains_key(id) || url_key_taken(&urls, id, entry.url_key()) {
+                continue;
+            }
+
+            urls.insert(id.clone(), entry);
+            imported.push(id.clone());
+        }
+
+        Ok(imported)
+    }
+
+    async fn bulk_insert(&self, links: &[(String, String)]) -> Result<u64, TinyUrlError> {
+        let mut urls = self.urls.write().await;
+        let mut inserted = HashMap::new();
+
+        for (id, url) in links {
+            if urls.contains_key(id) || inserted.contains_key(id) {
+                return Err(TinyUrlError::CodeAlreadyTaken(id.clone()));
+            }
+            let entry = Entry::imported(id, url);
+            if url_key_taken(&urls, id, entry.url_key())
+                || url_key_taken(&inserted, id, entry.url_key())
+            {
+                return Err(TinyUrlError::UrlAlreadyExists(url.clone()));
+            }
+            inserted.insert(id.clone(), entry);
+        }
+
+        let count = inserted.len() as u64;
+        urls.extend(inserted);
+
+        Ok(count)
+    }
+
+    fn export(&self) -> BoxStream<'static, Result<ExportRecord, TinyUrlError>> {
+        let urls = self.urls.clone();
+
+        stream::once(async move {
+            let mut records: Vec<ExportRecord> = urls
+                .read()
+                .await
+                .values()
+                .filter(|e| e.reserved_until.is_none())
+                .map(|e| ExportRecord {
+                    id: e.record.id.clone(),
+                    url: e.record.url.clone(),
+                    clicks: e.record.clicks,
+                    created_at: e.record.created_at,
+                    expires_at: e.expires_at,
+                    redirect_type: e.redirect_type,
+                    max_clicks: e.max_clicks,
+                    deleted_at: e.record.deleted_at,
+                    tags: e.record.tags.clone(),
+                    notes: e.record.notes.clone(),
+                })
+                .collect();
+            records.sort_by_key(|r| r.created_at);
+
+            stream::iter(records.into_iter().map(Ok))
+        })
+        .flatten()
+        .boxed()
+    }
+
+    fn sitemap(
+        &self,
+        page: u32,
+        per_page: u32,
+    ) -> BoxStream<'static, Result<(String, DateTime<Utc>), TinyUrlError>> {
+        let urls = self.urls.clone();
+        let offset = page.saturating_sub(1) as usize * per_page as usize;
+
+        stream::once(async move {
+            let mut links: Vec<_> = urls
+                .read()
+                .await
+                .values()
+                .filter(|e| e.is_live() && e.password_hash.is_none() && !e.private)
+                .map(|e| (e.record.id.clone(), e.record.created_at))
+                .collect();
+            links.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+
+            let page = links.into_iter().skip(offset).take(per_page as usize);
+            stream::iter(page.map(Ok))
+        })
+        .flatten()
+        .boxed()
+    }
+
+    async fn create_webhook(
+        &self,
+        target_url: &str,
+        req: &WebhookRequest,
+    ) -> Result<Webhook, TinyUrlError> {
+        let mut webhooks = self.webhooks.write().await;
+        let webhook = Webhook {
+            id: webhooks.len() as i64 + 1,
+            target_url: target_url.to_string(),
+            event: req.event,
+            url_id: req.url_id.clone(),
+            created_at: Utc::now(),
+        };
+        webhooks.push(webhook.clone());
+
+        Ok(webhook)
+    }
+
+    async fn list_webhooks(&self, event: WebhookEvent) -> Result<Vec<Webhook>, TinyUrlError> {
+        let webhooks = self.webhooks.read().await;
+
+        Ok(webhooks
+            .iter()
+            .filter(|w| w.event == event)
+            .cloned()
+            .collect())
+    }
+
+    async fn api_key_limit(&self, key: &str) -> Result<Option<u64>, TinyUrlError> {
+        Ok(self.api_keys.read().await.get(key).copied())
+    }
+
+    async fn ping(&self) -> Result<(), TinyUrlError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{auth::Actor, AppState, Config};
+
+    fn request(url: &str) -> ShortenRequest {
+        ShortenRequest {
+            url: url.to_string(),
+            ..Default::default()
+        }
+    }
+
+    async fn app_state(config: Config) -> AppState<InMemoryStore> {
+        AppState::new(InMemoryStore::new(), &config).await.unwrap()
+    }
+
+    fn actor() -> Actor {
+        Actor("api".to_string())
+    }
+
+    #[tokio::test]
+    async fn shorten_reuses_code_of_stored_url() {
+        let store = InMemoryStore::new();
+        let req = request("https://example.com/");
+
+        assert_eq!(
+            store.shorten("abc", &req.url, &req).await.unwrap(),
+            ("abc".to_string(), true)
+        );
+        assert_eq!(
+            store.shorten("def", &req.url, &req).await.unwrap(),
+            ("abc".to_string(), false)
+        );
+    }
+
+    #[tokio::test]
+    async fn shorten_rejects_taken_code() {
+        let store = InMemoryStore::new();
+        let req = request("https://example.com/");
+        store.shorten("abc", &req.url, &req).await.unwrap();
+
+        let other = request("https://example.org/");
+        let err = store.shorten("abc", &other.url, &other).await.unwrap_err();
+        assert!(matches!(err, TinyUrlError::CodeAlreadyTaken(_)));
+    }
+
+    #[tokio::test]
+    async fn retires_link_after_max_clicks() {
+        let store = InMemoryStore::new();
+        let req = ShortenRequest {
+            max_clicks: Some(1),
+            ..request("https://example.com/")
+        };
+        store.shorten("abc", &req.url, &req).await.unwrap();
+
+        assert!(store.get_url_by_id("abc", None, false).await.is_ok());
+        let err = store.get_url_by_id("abc", None, false).await.unwrap_err();
+        assert!(matches!(err, TinyUrlError::LinkExpired(_)));
+    }
+
+    #[tokio::test]
+    async fn deleted_links_are_tombstoned_until_purged() {
+        let store = InMemoryStore::new();
+        let req = request("https://example.com/");
+        store.shorten("abc", &req.url, &req).await.unwrap();
+        store.delete_url("abc").await.unwrap();
+
+        assert!(store.peek_url("abc", None).await.is_err());
+        assert_eq!(store.list_deleted_urls(1, 10).await.unwrap().1, 1);
+
+        assert_eq!(store.purge_deleted(Duration::ZERO).await.unwrap(), 1);
+        assert_eq!(store.list_deleted_urls(1, 10).await.unwrap().1, 0);
+    }
+
+    #[tokio::test]
+    async fn adjusts_click_counts_without_going_negative() {
+        let store = InMemoryStore::new();
+        let req = request("https://example.com/");
+        store.shorten("abc", &req.url, &req).await.unwrap();
+
+        assert_eq!(store.adjust_clicks("abc", 5, false).await.unwrap(), 5);
+        assert_eq!(store.adjust_clicks("abc", -7, false).await.unwrap(), 0);
+        store.adjust_clicks("abc", 3, false).await.unwrap();
+        assert_eq!(store.adjust_clicks("abc", 3, true).await.unwrap(), 0);
+        assert!(matches!(
+            store.adjust_clicks("nope", 1, false).await,
+            Err(TinyUrlError::IdNotFound(_))
+        ));
+
+        store.delete_url("abc").await.unwrap();
+        assert!(matches!(
+            store.adjust_clicks("abc", 1, false).await,
+            Err(TinyUrlError::IdNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn reservations_resolve_once_activated() {
+        let store = InMemoryStore::new();
+        let until = Utc::now() + TimeDelta::hours(1);
+        store.reserve("promo", until).await.unwrap();
+
+        assert!(store.peek_url("promo", None).await.is_err());
+        let req = request("https://example.com/");
+        let err = store.shorten("promo", &req.url, &req).await.unwrap_err();
+        assert!(matches!(err, TinyUrlError::CodeAlreadyTaken(_)));
+
+        store
+            .update_url("promo", Some(&req.url), &UpdateRequest::default())
+            .await
+            .unwrap();
+        assert_eq!(store.peek_url("promo", None).await.unwrap(), req.url);
+        assert_eq!(store.release_expired_reservations().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn lists_links_by_tag() {
+        let store = InMemoryStore::new();
+        let mut req = request("https://example.com/");
+        req.tags = Some(vec!["marketing".to_string()]);
+        store.shorten("abc", &req.url, &req).await.unwrap();
+        let other = request("https://example.org/");
+        store.shorten("def", &other.url, &other).await.unwrap();
+
+        let urls = store.list_urls(None, 10, Some("marketing")).await.unwrap();
+        assert_eq!(urls.len(), 1);
+        assert_eq!(urls[0].id, "abc");
+
+        let retag = UpdateRequest {
+            tags: Some(vec!["sales".to_string()]),
+            ..Default::default()
+        };
+        store.update_url("abc", None, &retag).await.unwrap();
+        assert!(store
+            .list_urls(None, 10, Some("marketing"))
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(store.peek_url("abc", None).await.unwrap(), req.url);
+    }
+
+    #[tokio::test]
+    async fn sums_global_stats() {
+        let store = InMemoryStore::new();
+        let req = ShortenRequest {
+            ttl_seconds: Some(60),
+            ..request("https://a.example/")
+        };
+        store.shorten("a", &req.url, &req).await.unwrap();
+        let req = request("https://b.example/");
+        store.shorten("b", &req.url, &req).await.unwrap();
+        store.get_url_by_id("b", None, false).await.unwrap();
+        store.get_url_by_id("b", None, false).await.unwrap();
+
+        let stats = store.global_stats().await.unwrap();
+        assert_eq!(stats.total_urls, 2);
+        assert_eq!(stats.total_clicks, 2);
+        assert_eq!(stats.urls_created_last_24h, 2);
+        assert_eq!(stats.urls_expiring_next_24h, 1);
+        assert_eq!(stats.top_10_urls[0].id, "b");
+    }
+
+    #[tokio::test]
+    async fn pages_links_after_cursor() {
+        let store = InMemoryStore::new();
+        for (id, url) in [("a", "https://a.example/"), ("b", "https://b.example/")] {
+            store.shorten(id, url, &request(url)).await.unwrap();
+        }
+        let req = request("https://c.example/");
+        store.shorten("c", &req.url, &req).await.unwrap();
+
+        let first = store.list_urls(None, 2, None).await.unwrap();
+        assert_eq!(first.len(), 2);
+
+        let after = Cursor::after(&first[1]);
+        let rest = store.list_urls(Some(&after), 2, None).await.unwrap();
+        let mut ids: Vec<_> = first.iter().chain(&rest).map(|r| r.id.as_str()).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, ["a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn counts_clicks_per_bucket() {
+        let store = InMemoryStore::new();
+        let info = ClickInfo {
+            ip: [127, 0, 0, 1].into(),
+            user_agent: None,
+            referer: None,
+        };
+        store.record_click("abc", &info).await.unwrap();
+        store.record_click("abc", &info).await.unwrap();
+        store.record_click("def", &info).await.unwrap();
+
+        let now = Utc::now();
+        let buckets = store
+            .click_timeseries(
+                "abc",
+                Granularity::Day,
+                now - TimeDelta::days(1),
+                now + TimeDelta::days(1),
+            )
+            .await
+            .unwrap();
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(buckets[0].clicks, 2);
+        assert_eq!(
+            buckets[0].bucket,
+            now.duration_trunc(TimeDelta::days(1)).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn restores_only_deleted_links() {
+        let store = InMemoryStore::new();
+        let req = request("https://example.com/");
+        store.shorten("abc", &req.url, &req).await.unwrap();
+
+        assert!(matches!(
+            store.restore_url("abc").await,
+            Err(TinyUrlError::IdNotFound(_))
+        ));
+
+        store.delete_url("abc").await.unwrap();
+        store.restore_url("abc").await.unwrap();
+        assert_eq!(store.peek_url("abc", None).await.unwrap(), req.url);
+    }
+
+    #[tokio::test]
+    async fn audit_log_outlives_purged_links() {
+        let store = InMemoryStore::new();
+        let req = request("https://example.com/");
+        store.shorten("abc", &req.url, &req).await.unwrap();
+        store
+            .record_audit(AuditAction::Create, "abc", "api")
+            .await
+            .unwrap();
+        store.delete_url("abc").await.unwrap();
+        store
+            .record_audit(AuditAction::Delete, "abc", "admin")
+            .await
+            .unwrap();
+        store.purge_deleted(Duration::ZERO).await.unwrap();
+
+        let (entries, total) = store
+            .list_audit(Some("abc"), None, None, 1, 10)
+            .await
+            .unwrap();
+        assert_eq!(total, 2);
+        assert!(matches!(entries[0].action, AuditAction::Delete));
+    }
+
+    #[tokio::test]
+    async fn app_shortens_and_audits_new_url() {
+        let state = app_state(Config::from_env()).await;
+
+        let (id, created) = state
+            .shorten(&request("HTTPS://Example.com/a/"), &actor())
+            .await
+            .unwrap();
+
+        assert!(created);
+        assert_eq!(id.len(), state.config.code_length);
+        assert_eq!(
+            state.store.peek_url(&id, None).await.unwrap(),
+            "https://example.com/a"
+        );
+        let (entries, _) = state
+            .store
+            .list_audit(Some(&id), None, None, 1, 10)
+            .await
+            .unwrap();
+        assert!(matches!(
+            entries[..],
+            [AuditEntry {
+                action: AuditAction::Create,
+                ..
+            }]
+        ));
+    }
+
+    #[tokio::test]
+    async fn app_returns_existing_code_for_stored_url() {
+        let state = app_state(Config::from_env()).await;
+        let req = request("https://example.com/");
+        let (id, _) = state.shorten(&req, &actor()).await.unwrap();
+
+        let (again, created) = state.shorten(&req, &actor()).await.unwrap();
+
+        assert_eq!(again, id);
+        assert!(!created);
+        assert_eq!(
+            state
+                .store
+                .list_audit(None, None, None, 1, 10)
+                .await
+                .unwrap()
+                .1,
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn app_does_not_reuse_code_for_request_with_options() {
+        let state = app_state(Config::from_env()).await;
+        let (public, _) = state
+            .shorten(&request("https://example.com/"), &actor())
+            .await
+            .unwrap();
+        let req = ShortenRequest {
+            password: Some("hunter2".to_string()),
+            ..request("https://example.com/")
+        };
+
+        let (protected, created) = state.shorten(&req, &actor()).await.unwrap();
+
+        assert!(created);
+        assert_ne!(protected, public);
+        let err = state
+            .get_url_by_id(&protected, None, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, TinyUrlError::PasswordRequired(_)));
+        // nor is the protected link handed out to a plain request
+        let (again, created) = state
+            .shorten(&request("https://example.com/"), &actor())
+            .await
+            .unwrap();
+        assert_eq!((again, created), (public, false));
+    }
+
+    #[tokio::test]
+    async fn app_rejects_vanity_code_for_url_stored_under_another_code() {
+        let state = app_state(Config::from_env()).await;
+        let (id, _) = state
+            .shorten(&request("https://example.com/"), &actor())
+            .await
+            .unwrap();
+        let req = ShortenRequest {
+            code: Some("mycode".to_string()),
+            ..request("https://example.com/")
+        };
+
+        let err = state.shorten(&req, &actor()).await.unwrap_err();
+
+        assert!(matches!(err, TinyUrlError::UrlAlreadyExists(_)));
+        assert!(matches!(
+            state.store.get_stats("mycode", true).await,
+            Err(TinyUrlError::IdNotFound(_))
+        ));
+        assert_eq!(
+            state
+                .store
+                .find_by_url("https://example.com/")
+                .await
+                .unwrap(),
+            id
+        );
+    }
+
+    #[tokio::test]
+    async fn app_gives_up_after_max_retries() {
+        let mut config = Config::from_env();
+        config.code_length = 1;
+        config.id_alphabet = vec!['a'];
+        config.max_retries = 2;
+        config.retry_base_delay = Duration::ZERO;
+        let state = app_state(config).await;
+        let taken = request("https://example.com/");
+        state.store.shorten("a", &taken.url, &taken).await.unwrap();
+
+        let err = state
+            .shorten(&request("https://example.org/"), &actor())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            TinyUrlError::TooManyShortenRetries {
+                attempted: 2,
+                max: 2
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn app_rejects_invalid_url() {
+        let state = app_state(Config::from_env()).await;
+
+        let err = state
+            .shorten(&request("ftp://example.com/"), &actor())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, TinyUrlError::InvalidUrl(_)));
+        assert!(state
+            .store
+            .list_urls(None, 10, None)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn app_rejects_blocked_domain() {
+        let mut config = Config::from_env();
+        config.blocked_domains = vec!["example.com".to_string()];
+        let state = app_state(config).await;
+
+        let err = state
+            .shorten(&request("https://www.example.com/"), &actor())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, TinyUrlError::BlockedDomain(domain) if domain == "example.com"));
+    }
+
+    #[tokio::test]
+    async fn app_retires_single_click_link() {
+        let state = app_state(Config::from_env()).await;
+        let req = ShortenRequest {
+            max_clicks: Some(1),
+            ..request("https://example.com/")
+        };
+        let (id, _) = state.shorten(&req, &actor()).await.unwrap();
+
+        assert!(state.get_url_by_id(&id, None, None).await.is_ok());
+        let err = state.get_url_by_id(&id, None, None).await.unwrap_err();
+        assert!(matches!(err, TinyUrlError::LinkExpired(_)));
+    }
+
+    #[tokio::test]
+    async fn app_shortens_concurrent_replays_once() {
+        let state = app_state(Config::from_env()).await;
+        let key = Uuid::from_u128(42);
+        // private links are never shared, so each replay that ran would create its own
+        let req = ShortenRequest {
+            private: true,
+            ..request("https://example.com/")
+        };
+        let replay = || {
+            let (state, req) = (state.clone(), req.clone());
+            tokio::spawn(async move { state.shorten_idempotent(key, &req, &actor()).await })
+        };
+
+        // both replays are in flight before either can store a link
+        let urls = state.store.urls.write().await;
+        let (first, second) = (replay(), replay());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        drop(urls);
+
+        let (id, created) = first.await.unwrap().unwrap();
+        assert!(created);
+        assert!(matches!(
+            second.await.unwrap(),
+            Err(TinyUrlError::IdempotencyKeyInUse(_))
+        ));
+        assert_eq!(replay().await.unwrap().unwrap(), (id, false));
+        assert_eq!(
+            state.store.list_urls(None, 10, None).await.unwrap().len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn app_checks_allowed_referers() {
+        let state = app_state(Config::from_env()).await;
+        let req = ShortenRequest {
+            allowed_referers: Some(vec!["https://blog.example.org/posts/1".to_string()]),
+            ..request("https://example.com/")
+        };
+        let (id, _) = state.shorten(&req, &actor()).await.unwrap();
+
+        for referer in [None, Some("https://example.net")] {
+            let err = state.get_url_by_id(&id, referer, None).await.unwrap_err();
+            assert!(matches!(err, TinyUrlError::RefererNotAllowed(_)));
+        }
+        // the second lookup is served from the cache, which checks the referer too
+        for _ in 0..2 {
+            let target = state
+                .get_url_by_id(&id, Some("https://blog.example.org"), None)
+                .await
+                .unwrap();
+            assert_eq!(target.url, "https://example.com/");
+        }
+        let err = state.get_url_by_id(&id, None, None).await.unwrap_err();
+        assert!(matches!(err, TinyUrlError::RefererNotAllowed(_)));
+
+        let req = ShortenRequest {
+            allowed_referers: Some(vec!["blog.example.org".to_string()]),
+            ..request("https://example.com/other")
+        };
+        let err = state.shorten(&req, &actor()).await.unwrap_err();
+        assert!(matches!(err, TinyUrlError::InvalidUrl(_)));
+    }
+
+    #[tokio::test]
+    async fn app_requires_password_of_protected_link() {
+        let state = app_state(Config::from_env()).await;
+        let req = ShortenRequest {
+            password: Some("hunter2".to_string()),
+            ..request("https://example.com/")
+        };
+        let (id, _) = state.shorten(&req, &actor()).await.unwrap();
+
+        for password in [None, Some("hunter3")] {
+            let err = state.get_url_by_id(&id, None, password).await.unwrap_err();
+            assert!(matches!(err, TinyUrlError::PasswordRequired(_)));
+        }
+        // protected links are never cached, so every lookup checks the password
+        for _ in 0..2 {
+            let target = state
+                .get_url_by_id(&id, None, Some("hunter2"))
+                .await
+                .unwrap();
+            assert_eq!(target.url, "https://example.com/");
+        }
+        let err = state.get_url_by_id(&id, None, None).await.unwrap_err();
+        assert!(matches!(err, TinyUrlError::PasswordRequired(_)));
+
+        assert!(matches!(
+            state.store.peek_url(&id, None).await,
+            Err(TinyUrlError::PasswordRequired(_))
+        ));
+        assert!(matches!(
+            state.store.get_stats(&id, false).await,
+            Err(TinyUrlError::PasswordRequired(_))
+        ));
+        assert_eq!(state.store.get_stats(&id, true).await.unwrap().clicks, 2);
+    }
+
+    #[tokio::test]
+    async fn app_does_not_resolve_expired_link() {
+        let state = app_state(Config::from_env()).await;
+        let req = ShortenRequest {
+            ttl_seconds: Some(0),
+            ..request("https://example.com/")
+        };
+        let (id, created) = state.shorten(&req, &actor()).await.unwrap();
+
+        assert!(created);
+        let err = state.get_url_by_id(&id, None, None).await.unwrap_err();
+        assert!(matches!(err, TinyUrlError::IdNotFound(_)));
+    }
+
+    #[tokio::test]
+    async fn app_does_not_reuse_dead_link() {
+        let state = app_state(Config::from_env()).await;
+        let expired = ShortenRequest {
+            ttl_seconds: Some(0),
+            ..request("https://example.com/")
+        };
+        let (expired, _) = state.shorten(&expired, &actor()).await.unwrap();
+        let used_up = ShortenRequest {
+            max_clicks: Some(1),
+            ..request("https://example.org/")
+        };
+        let (used_up, _) = state.shorten(&used_up, &actor()).await.unwrap();
+        state.get_url_by_id(&used_up, None, None).await.unwrap();
+
+        for (dead, url) in [
+            (expired, "https://example.com/"),
+            (used_up, "https://example.org/"),
+        ] {
+            let (id, created) = state.shorten(&request(url), &actor()).await.unwrap();
+            assert!(created);
+            assert_ne!(id, dead);
+            assert_eq!(state.store.find_by_url(url).await.unwrap(), id);
+        }
+    }
+}
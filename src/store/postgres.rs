@@ -0,0 +1,1392 @@
+use std::{future::Future, time::Duration};
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::BoxStream;
+use metrics::{counter, gauge};
+use sqlx::{
+    postgres::{PgDatabaseError, PgPoolOptions},
+    FromRow, PgPool,
+};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use super::{forward, is_connection_error, received, timed_query, UrlStore};
+use crate::{
+    redact_password, AuditAction, AuditEntry, Click, ClickBucket, ClickInfo, Config, Cursor,
+    DomainStats, ExportRecord, GlobalStats, Granularity, RedirectTarget, SearchHit, ShortenRequest,
+    TinyUrlError, UpdateRequest, UrlRecord, Webhook, WebhookEvent, WebhookRequest,
+};
+
+/// How often `report_pool_usage` samples the pools.
+const POOL_METRICS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Rows buffered between the export query and a slow client.
+const EXPORT_BUFFER: usize = 256;
+
+/// Streams `(id, url)` rows in the binary format written by `copy_row`.
+const COPY_URLS: &str = "COPY urls (id, url) FROM STDIN WITH (FORMAT binary)";
+
+/// Bytes of `COPY` data collected before they are sent to the server.
+const COPY_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Signature, flags and header extension length opening binary `COPY` data.
+const COPY_HEADER: &[u8] = b"PGCOPY\n\xff\r\n\0\0\0\0\0\0\0\0\0";
+
+/// Field count of -1 marking the end of binary `COPY` data.
+const COPY_TRAILER: &[u8] = &(-1i16).to_be_bytes();
+
+/// Appends a tuple of text `fields` to binary `COPY` data, each one prefixed with its length.
+fn copy_row(buf: &mut Vec<u8>, fields: &[&str]) {
+    buf.extend_from_slice(&(fields.len() as i16).to_be_bytes());
+    for field in fields {
+        buf.extend_from_slice(&(field.len() as i32).to_be_bytes());
+        buf.extend_from_slice(field.as_bytes());
+    }
+}
+
+/// Reports a `COPY` that broke a unique index as the conflict the other stores report.
+fn copy_error(e: sqlx::Error) -> TinyUrlError {
+    let sqlx::Error::Database(db_error) = &e else {
+        return e.into();
+    };
+    // the detail names the duplicate key: `Key (id)=(abc) already exists.`
+    let key = db_error
+        .try_downcast_ref::<PgDatabaseError>()
+        .and_then(|e| e.detail())
+        .and_then(|detail| detail.split_once(")=("))
+        .and_then(|(_, key)| key.strip_suffix(") already exists."))
+        .unwrap_or_default();
+
+    match db_error.constraint() {
+        Some("urls_pkey") => TinyUrlError::CodeAlreadyTaken(key.to_string()),
+        // keyed on `(url, redirect_type)`
+        Some("urls_url_live_key") => {
+            let url = key.rsplit_once(", ").map_or(key, |(url, _)| url);
+            TinyUrlError::UrlAlreadyExists(url.to_string())
+        }
+        _ => e.into(),
+    }
+}
+
+/// Fails with `PoolExhausted` when every connection of `pool` is checked out.
+fn check_pool(pool: &PgPool) -> Result<(), TinyUrlError> {
+    // `try_acquire` would also fail while the pool is still below its maximum and simply
+    // has no idle connection yet, so check for a full pool instead
+    if pool.num_idle() == 0 && pool.size() >= pool.options().get_max_connections() {
+        counter!("pool_exhaustion_total").increment(1);
+        return Err(TinyUrlError::PoolExhausted);
+    }
+
+    Ok(())
+}
+
+/// One of the most clicked links, with the totals of `global_stats` alongside.
+#[derive(Debug, FromRow)]
+struct TopUrl {
+    #[sqlx(flatten)]
+    record: UrlRecord,
+    total_urls: i64,
+    total_clicks: i64,
+    urls_created_last_24h: i64,
+    urls_expiring_next_24h: i64,
+}
+
+/// A link's record and whether a password protects it, as read by `get_stats`.
+#[derive(Debug, FromRow)]
+struct StatsRow {
+    #[sqlx(flatten)]
+    record: UrlRecord,
+    password_protected: bool,
+}
+
+/// Store on a primary pool that takes all writes, and optionally a read replica that serves
+/// lookups which do not need to see the latest write. Redirects stay on the primary, since
+/// resolving one also counts the click.
+#[derive(Debug, Clone)]
+pub struct PostgresStore {
+    db: PgPool,
+    read_db: Option<PgPool>,
+    slow_query_threshold: Duration,
+}
+
+impl PostgresStore {
+    /// Connects the pools and applies pending migrations to the primary.
+    ///
+    /// A replica at `TINYURL_DB_READ_ADDR` that cannot be reached is left out with a warning
+    /// rather than failing startup.
+    pub async fn connect(config: &Config) -> Result<Self, TinyUrlError> {
+        let db = Self::connect_pool(config, &config.database_url).await?;
+        sqlx::migrate!().run(&db).await?;
+
+        let read_db = match &config.db_read_url {
+            Some(url) => match Self::connect_pool(config, url).await {
+                Ok(pool) => Some(pool),
+                Err(e) => {
+                    warn!("Read replica unavailable, reading from the primary: {}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        Ok(Self {
+            db,
+            read_db,
+            slow_query_threshold: config.slow_query_threshold,
+        })
+    }
+
+    async fn connect_pool(config: &Config, url: &str) -> Result<PgPool, TinyUrlError> {
+        let pool = PgPoolOptions::new()
+            .max_connections(config.db_max_connections)
+            .acquire_timeout(config.db_connect_timeout)
+            .connect(url)
+            .await?;
+        info!(
+            "Connected to database: {} (max connections: {}, acquire timeout: {:?})",
+            redact_password(url),
+            config.db_max_connections,
+            config.db_connect_timeout
+        );
+
+        Ok(pool)
+    }
+
+    pub async fn close(&self) {
+        self.db.close().await;
+        if let Some(read_db) = &self.read_db {
+            read_db.close().await;
+        }
+    }
+
+    /// Every `POOL_METRICS_INTERVAL`, publishes the `db_pool_size`, `db_pool_idle` and
+    /// `db_pool_acquired` gauges, labelled with the `primary` or `replica` pool, so that leaked
+    /// connections show up before the pool runs dry.
+    pub async fn report_pool_usage(self) {
+        let mut interval = tokio::time::interval(POOL_METRICS_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            let pools = std::iter::once(("primary", &self.db))
+                .chain(self.read_db.iter().map(|db| ("replica", db)));
+            for (name, db) in pools {
+                let size = db.size();
+                // idle connections are counted separately, so the two can be momentarily off
+                let idle = u32::try_from(db.num_idle()).unwrap_or(size).min(size);
+                gauge!("db_pool_size", "pool" => name).set(size);
+                gauge!("db_pool_idle", "pool" => name).set(idle);
+                gauge!("db_pool_acquired", "pool" => name).set(size - idle);
+            }
+        }
+    }
+
+    /// Awaits the store operation `f` named `name` on the primary, timed by `timed_query`.
+    ///
+    /// Fails immediately with `PoolExhausted` when every connection of the primary is checked
+    /// out, rather than queueing for up to the acquire timeout behind the other requests.
+    async fn query<T>(
+        &self,
+        name: &str,
+        f: impl Future<Output = Result<T, TinyUrlError>>,
+    ) -> Result<T, TinyUrlError> {
+        check_pool(&self.db)?;
+
+        timed_query(name, self.slow_query_threshold, f).await
+    }
+
+    /// Like `query`, but runs the read-only `query` named `name` on the replica if there is
+    /// one, repeating it on the primary when the replica cannot be reached. Only the pool
+    /// that runs it has to have a connection to spare.
+    async fn read<T, F, Fut>(&self, name: &str, query: F) -> Result<T, TinyUrlError>
+    where
+        F: Fn(PgPool) -> Fut,
+        Fut: Future<Output = Result<T, sqlx::Error>>,
+    {
+        if let Some(read_db) = &self.read_db {
+            check_pool(read_db)?;
+            match timed_query(name, self.slow_query_threshold, query(read_db.clone())).await {
+                Err(e) if is_connection_error(&e) => {
+                    warn!("Read replica unavailable, reading from the primary: {}", e);
+                }
+                res => return Ok(res?),
+            }
+        }
+
+        check_pool(&self.db)?;
+        Ok(timed_query(name, self.slow_query_threshold, query(self.db.clone())).await?)
+    }
+
+    /// Tells a link that used up its clicks apart from one that never existed.
+    async fn missing(&self, id: &str, referer: Option<&str>) -> Result<TinyUrlError, TinyUrlError> {
+        let row: Option<(bool, bool, bool, bool)> = db_query!(
+            "missing",
+            sqlx::query_as(
+                r#"
+                SELECT
+                    COALESCE(clicks >= max_clicks, FALSE),
+                    deleted_at IS NULL
+                      AND reserved_until IS NULL
+                      AND (expires_at IS NULL OR expires_at > NOW()),
+                    cardinality(allowed_referers) = 0
+                      OR COALESCE($2 = ANY(allowed_referers), FALSE),
+                    password_hash IS NOT NULL
+                FROM urls WHERE id = $1
+                "#,
+            )
+            .bind(id)
+            .bind(referer),
+            |query| query.fetch_optional(&self.db)
+        )
+        .await?;
+
+        let err = match row {
+            Some((true, _, _, _)) => TinyUrlError::LinkExpired(id.to_string()),
+            Some((_, true, false, _)) => TinyUrlError::RefererNotAllowed(id.to_string()),
+            Some((_, true, true, true)) => TinyUrlError::PasswordRequired(id.to_string()),
+            _ => TinyUrlError::IdNotFound(id.to_string()),
+        };
+        Ok(err)
+    }
+}
+
+#[async_trait]
+impl UrlStore for PostgresStore {
+    async fn shorten(
+        &self,
+        id: &str,
+        url: &str,
+        req: &ShortenRequest,
+    ) -> Result<(String, bool), TinyUrlError> {
+        self.query("shorten", async {
+            // The lookup of a link to reuse and the insert run as one statement: the CTE
+            // yields that link if there is one, otherwise the new row if the insert went
+            // through. Only plain links are reused, the ones `urls_url_live_key` covers, so
+            // the options of a request are never dropped; they cannot expire or run out
+            // either, so dead links are neither reused nor block the insert.
+            let res: Option<(String, bool)> = db_query!(
+                "shorten",
+                sqlx::query_as(
+                    r#"
+                    WITH existing AS (
+                        SELECT id FROM urls
+                        WHERE $11
+                          AND url = $2
+                          AND redirect_type = $4
+                          AND deleted_at IS NULL
+                          AND reserved_until IS NULL
+                          AND expires_at IS NULL
+                          AND max_clicks IS NULL
+                          AND password_hash IS NULL
+                          AND cardinality(allowed_referers) = 0
+                          AND NOT private
+                    ),
+                    inserted AS (
+                        INSERT INTO urls (
+                            id, url, expires_at, redirect_type, max_clicks, tags, notes,
+                            allowed_referers, password_hash, private
+                        )
+                        SELECT
+                            $1, $2, NOW() + $3 * INTERVAL '1 second', $4, $5, $6,
+                            NULLIF($7, ''), $8, $9, $10
+                        WHERE NOT EXISTS (SELECT 1 FROM existing)
+                        ON CONFLICT DO NOTHING
+                        RETURNING id
+                    )
+                    SELECT id, FALSE FROM existing
+                    UNION ALL
+                    SELECT id, TRUE FROM inserted
+                    "#,
+                )
+                .bind(id)
+                .bind(url)
+                .bind(req.ttl_seconds_i64())
+                .bind(req.redirect_type)
+                .bind(req.max_clicks_i32())
+                .bind(req.tags.as_deref().unwrap_or_default())
+                .bind(req.notes.as_deref())
+                .bind(req.referer_origins())
+                .bind(req.password_hash.as_deref())
+                .bind(req.private)
+                .bind(req.reusable()),
+                |query| query.fetch_optional(&self.db)
+            )
+            .await?;
+
+            if let Some(res) = res {
+                return Ok(res);
+            }
+            if !req.reusable() {
+                return Err(TinyUrlError::CodeAlreadyTaken(id.to_string()));
+            }
+
+            // Either the code is in use, or a concurrent request stored the url after the
+            // snapshot of the statement was taken, in which case the insert waited for it to
+            // commit but the select could not see it. A new statement sees the committed row.
+            let existing: Option<String> = db_query!(
+                "shorten",
+                sqlx::query_scalar(
+                    r#"
+                    SELECT id FROM urls
+                    WHERE url = $1
+                      AND redirect_type = $2
+                      AND deleted_at IS NULL
+                      AND reserved_until IS NULL
+                      AND expires_at IS NULL
+                      AND max_clicks IS NULL
+                      AND password_hash IS NULL
+                      AND cardinality(allowed_referers) = 0
+                      AND NOT private
+                    "#,
+                )
+                .bind(url)
+                .bind(req.redirect_type),
+                |query| query.fetch_optional(&self.db)
+            )
+            .await?;
+
+            existing
+                .map(|existing| (existing, false))
+                .ok_or(TinyUrlError::CodeAlreadyTaken(id.to_string()))
+        })
+        .await
+    }
+
+    async fn reserve(&self, id: &str, until: DateTime<Utc>) -> Result<(), TinyUrlError> {
+        self.query("reserve", async {
+            let res: Option<String> = db_query!(
+                "reserve",
+                sqlx::query_scalar(
+                    r#"
+                    INSERT INTO urls (id, url, reserved_until) VALUES ($1, '', $2)
+                    ON CONFLICT DO NOTHING
+                    RETURNING id
+                    "#,
+                )
+                .bind(id)
+                .bind(until),
+                |query| query.fetch_optional(&self.db)
+            )
+            .await?;
+
+            res.map(|_| ())
+                .ok_or(TinyUrlError::CodeAlreadyTaken(id.to_string()))
+        })
+        .await
+    }
+
+    async fn delete_expired(&self) -> Result<u64, TinyUrlError> {
+        self.query("delete_expired", async {
+            let res = db_query!(
+                "delete_expired",
+                sqlx::query(
+                    r#"
+                    DELETE FROM urls
+                    WHERE (expires_at IS NOT NULL AND expires_at < NOW())
+                       OR (max_clicks IS NOT NULL AND clicks >= max_clicks)
+                    "#,
+                ),
+                |query| query.execute(&self.db)
+            )
+            .await?;
+
+            Ok(res.rows_affected())
+        })
+        .await
+    }
+
+    async fn release_expired_reservations(&self) -> Result<u64, TinyUrlError> {
+        self.query("release_expired_reservations", async {
+            let res = db_query!(
+                "release_expired_reservations",
+                sqlx::query(
+                    r#"
+                    DELETE FROM urls WHERE reserved_until IS NOT NULL AND reserved_until < NOW()
+                    "#,
+                ),
+                |query| query.execute(&self.db)
+            )
+            .await?;
+
+            Ok(res.rows_affected())
+        })
+        .await
+    }
+
+    async fn get_url_by_id(
+        &self,
+        id: &str,
+        referer: Option<&str>,
+        unlocked: bool,
+    ) -> Result<RedirectTarget, TinyUrlError> {
+        self.query("get_url_by_id", async {
+            // count the click in the same statement that resolves the url, retiring the link
+            // once its last allowed click is used; a restored or adjusted link can be live
+            // with its clicks used up, and stays expired
+            let target: Option<RedirectTarget> = db_query!(
+                "get_url_by_id",
+                sqlx::query_as(
+                    r#"
+                    UPDATE urls SET
+                        clicks = clicks + 1,
+                        deleted_at = CASE WHEN clicks + 1 >= max_clicks THEN NOW() END
+                    WHERE id = $1
+                      AND deleted_at IS NULL
+                      AND reserved_until IS NULL
+                      AND (expires_at IS NULL OR expires_at > NOW())
+                      AND (max_clicks IS NULL OR clicks < max_clicks)
+                      AND (cardinality(allowed_referers) = 0 OR $2 = ANY(allowed_referers))
+                      AND ($3 OR password_hash IS NULL)
+                    RETURNING
+                        url, redirect_type, expires_at, max_clicks, created_at, allowed_referers,
+                        password_hash IS NOT NULL AS password_protected
+                    "#,
+                )
+                .bind(id)
+                .bind(referer)
+                .bind(unlocked),
+                |query| query.fetch_optional(&self.db)
+            )
+            .await?;
+
+            match target {
+                Some(target) => Ok(target),
+                None => Err(self.missing(id, referer).await?),
+            }
+        })
+        .await
+    }
+
+    async fn password_hash(&self, id: &str) -> Result<Option<String>, TinyUrlError> {
+        self.query("password_hash", async {
+            let hash: Option<Option<String>> = db_query!(
+                "password_hash",
+                sqlx::query_scalar(
+                    r#"
+                    SELECT password_hash FROM urls
+                    WHERE id = $1
+                      AND deleted_at IS NULL
+                      AND reserved_until IS NULL
+                      AND (expires_at IS NULL OR expires_at > NOW())
+                    "#,
+                )
+                .bind(id),
+                |query| query.fetch_optional(&self.db)
+            )
+            .await?;
+
+            hash.ok_or(TinyUrlError::IdNotFound(id.to_string()))
+        })
+        .await
+    }
+
+    async fn peek_url(&self, id: &str, referer: Option<&str>) -> Result<String, TinyUrlError> {
+        let url: Option<(String, bool, bool)> = self
+            .read("peek_url", |db| async move {
+                db_query!(
+                    "peek_url",
+                    sqlx::query_as(
+                        r#"
+                        SELECT
+                            url,
+                            cardinality(allowed_referers) = 0
+                              OR COALESCE($2 = ANY(allowed_referers), FALSE),
+                            password_hash IS NOT NULL
+                        FROM urls
+                        WHERE id = $1
+                          AND deleted_at IS NULL
+                          AND reserved_until IS NULL
+                          AND (expires_at IS NULL OR expires_at > NOW())
+                        "#,
+                    )
+                    .bind(id)
+                    .bind(referer),
+                    |query| query.fetch_optional(&db)
+                )
+                .await
+            })
+            .await?;
+
+        match url {
+            Some((_, false, _)) => Err(TinyUrlError::RefererNotAllowed(id.to_string())),
+            Some((url, true, false)) => Ok(url),
+            Some((_, true, true)) => Err(TinyUrlError::PasswordRequired(id.to_string())),
+            None => Err(TinyUrlError::IdNotFound(id.to_string())),
+        }
+    }
+
+    async fn find_by_url(&self, url: &str) -> Result<String, TinyUrlError> {
+        self.query("find_by_url", async {
+            let id = db_query!(
+                "find_by_url",
+                sqlx::query_scalar(
+                    r#"
+                    SELECT id FROM urls
+                    WHERE url = $1
+                          AND deleted_at IS NULL
+                          AND reserved_until IS NULL
+                          AND expires_at IS NULL
+                          AND max_clicks IS NULL
+                          AND password_hash IS NULL
+                          AND cardinality(allowed_referers) = 0
+                          AND NOT private
+                    ORDER BY created_at
+                    LIMIT 1
+                    "#,
+                )
+                .bind(url),
+                |query| query.fetch_optional(&self.db)
+            )
+            .await?;
+
+            id.ok_or(TinyUrlError::IdNotFound(url.to_string()))
+        })
+        .await
+    }
+
+    async fn count_click(&self, id: &str) -> Result<(), TinyUrlError> {
+        self.query("count_click", async {
+            db_query!(
+                "count_click",
+                sqlx::query(
+                    r#"
+                    UPDATE urls SET clicks = clicks + 1 WHERE id = $1
+                    "#,
+                )
+                .bind(id),
+                |query| query.execute(&self.db)
+            )
+            .await?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn record_click(&self, id: &str, info: &ClickInfo) -> Result<(), TinyUrlError> {
+        self.query("record_click", async {
+            db_query!(
+                "record_click",
+                sqlx::query(
+                    r#"
+                    INSERT INTO clicks (url_id, ip_address, user_agent, referer)
+                    VALUES ($1, $2::inet, $3, $4)
+                    "#,
+                )
+                .bind(id)
+                .bind(info.ip.to_string())
+                .bind(&info.user_agent)
+                .bind(&info.referer),
+                |query| query.execute(&self.db)
+            )
+            .await?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list_clicks(
+        &self,
+        id: &str,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<Vec<Click>, TinyUrlError> {
+        self.query("list_clicks", async {
+            let clicks = db_query!(
+                "list_clicks",
+                sqlx::query_as(
+                    r#"
+                    SELECT clicked_at, host(ip_address) AS ip_address, user_agent, referer
+                    FROM clicks
+                    WHERE url_id = $1
+                      AND ($2::timestamptz IS NULL OR clicked_at >= $2)
+                      AND ($3::timestamptz IS NULL OR clicked_at < $3)
+                    ORDER BY clicked_at DESC
+                    "#,
+                )
+                .bind(id)
+                .bind(from)
+                .bind(to),
+                |query| query.fetch_all(&self.db)
+            )
+            .await?;
+
+            Ok(clicks)
+        })
+        .await
+    }
+
+    async fn click_timeseries(
+        &self,
+        id: &str,
+        granularity: Granularity,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<ClickBucket>, TinyUrlError> {
+        self.read("click_timeseries", |db| async move {
+            db_query!(
+                "click_timeseries",
+                sqlx::query_as(
+                    r#"
+                    SELECT date_trunc($2, clicked_at AT TIME ZONE 'UTC') AT TIME ZONE 'UTC'
+                               AS bucket,
+                           COUNT(*) AS clicks
+                    FROM clicks
+                    WHERE url_id = $1 AND clicked_at >= $3 AND clicked_at < $4
+                    GROUP BY bucket
+                    ORDER BY bucket
+                    "#,
+                )
+                .bind(id)
+                .bind(granularity.as_str())
+                .bind(from)
+                .bind(to),
+                |query| query.fetch_all(&db)
+            )
+            .await
+        })
+        .await
+    }
+
+    async fn get_idempotency_key(
+        &self,
+        key: Uuid,
+        ttl: Duration,
+    ) -> Result<Option<(String, Option<String>)>, TinyUrlError> {
+        self.query("get_idempotency_key", async {
+            let entry = db_query!(
+                "get_idempotency_key",
+                sqlx::query_as(
+                    r#"
+                    SELECT request, url_id FROM idempotency_keys
+                    WHERE key = $1 AND created_at > NOW() - $2 * INTERVAL '1 second'
+                    "#,
+                )
+                .bind(key)
+                .bind(ttl.as_secs_f64()),
+                |query| query.fetch_optional(&self.db)
+            )
+            .await?;
+
+            Ok(entry)
+        })
+        .await
+    }
+
+    async fn claim_idempotency_key(
+        &self,
+        key: Uuid,
+        request: &str,
+        ttl: Duration,
+    ) -> Result<bool, TinyUrlError> {
+        self.query("claim_idempotency_key", async {
+            let res = db_query!(
+                "claim_idempotency_key",
+                sqlx::query(
+                    r#"
+                    INSERT INTO idempotency_keys (key, request) VALUES ($1, $2)
+                    ON CONFLICT (key) DO UPDATE
+                    SET request = EXCLUDED.request, url_id = NULL, created_at = NOW()
+                    WHERE idempotency_keys.created_at <= NOW() - $3 * INTERVAL '1 second'
+                    "#,
+                )
+                .bind(key)
+                .bind(request)
+                .bind(ttl.as_secs_f64()),
+                |query| query.execute(&self.db)
+            )
+            .await?;
+
+            Ok(res.rows_affected() == 1)
+        })
+        .await
+    }
+
+    async fn save_idempotency_key(&self, key: Uuid, id: &str) -> Result<(), TinyUrlError> {
+        self.query("save_idempotency_key", async {
+            db_query!(
+                "save_idempotency_key",
+                sqlx::query(
+                    r#"
+                    UPDATE idempotency_keys SET url_id = $2 WHERE key = $1
+                    "#,
+                )
+                .bind(key)
+                .bind(id),
+                |query| query.execute(&self.db)
+            )
+            .await?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn release_idempotency_key(&self, key: Uuid) -> Result<(), TinyUrlError> {
+        self.query("release_idempotency_key", async {
+            db_query!(
+                "release_idempotency_key",
+                sqlx::query(
+                    r#"
+                    DELETE FROM idempotency_keys WHERE key = $1 AND url_id IS NULL
+                    "#,
+                )
+                .bind(key),
+                |query| query.execute(&self.db)
+            )
+            .await?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn purge_idempotency_keys(&self, ttl: Duration) -> Result<(), TinyUrlError> {
+        self.query("purge_idempotency_keys", async {
+            db_query!(
+                "purge_idempotency_keys",
+                sqlx::query(
+                    r#"
+                    DELETE FROM idempotency_keys WHERE created_at < NOW() - $1 * INTERVAL '1 second'
+                    "#,
+                )
+                .bind(ttl.as_secs_f64()),
+                |query| query.execute(&self.db)
+            )
+            .await?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn update_url(
+        &self,
+        id: &str,
+        url: Option<&str>,
+        req: &UpdateRequest,
+    ) -> Result<(), TinyUrlError> {
+        self.query("update_url", async {
+            let res = db_query!(
+                "update_url",
+                sqlx::query(
+                    r#"
+                    UPDATE urls SET
+                        url = COALESCE($1, url),
+                        reserved_until = CASE WHEN $1::text IS NULL THEN reserved_until END,
+                        tags = COALESCE($3, tags),
+                        notes = CASE WHEN $4::text IS NULL THEN notes ELSE NULLIF($4, '') END
+                    WHERE id = $2
+                      AND deleted_at IS NULL
+                      AND (reserved_until IS NULL OR reserved_until > NOW())
+                    "#,
+                )
+                .bind(url)
+                .bind(id)
+                .bind(req.tags.as_deref())
+                .bind(req.notes.as_deref()),
+                |query| query.execute(&self.db)
+            )
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::Database(e) if e.is_unique_violation() => {
+                    TinyUrlError::UrlAlreadyExists(url.unwrap_or_default().to_string())
+                }
+                e => e.into(),
+            })?;
+
+            if res.rows_affected() == 0 {
+                return Err(TinyUrlError::IdNotFound(id.to_string()));
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_url(&self, id: &str) -> Result<(), TinyUrlError> {
+        self.query("delete_url", async {
+            let deleted: Option<String> = db_query!(
+                "delete_url",
+                sqlx::query_scalar(
+                    r#"
+                    UPDATE urls SET deleted_at = NOW()
+                    WHERE id = $1 AND deleted_at IS NULL
+                    RETURNING id
+                    "#,
+                )
+                .bind(id),
+                |query| query.fetch_optional(&self.db)
+            )
+            .await?;
+
+            deleted
+                .map(|_| ())
+                .ok_or(TinyUrlError::IdNotFound(id.to_string()))
+        })
+        .await
+    }
+
+    async fn restore_url(&self, id: &str) -> Result<(), TinyUrlError> {
+        self.query("restore_url", async {
+            let restored: Option<String> = match db_query!(
+                "restore_url",
+                sqlx::query_scalar(
+                    r#"
+                    UPDATE urls SET deleted_at = NULL
+                    WHERE id = $1 AND deleted_at IS NOT NULL
+                    RETURNING id
+                    "#,
+                )
+                .bind(id),
+                |query| query.fetch_optional(&self.db)
+            )
+            .await
+            {
+                Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+                    let url = db_query!(
+                        "restore_url",
+                        sqlx::query_scalar("SELECT url FROM urls WHERE id = $1").bind(id),
+                        |query| query.fetch_one(&self.db)
+                    )
+                    .await?;
+                    return Err(TinyUrlError::UrlAlreadyExists(url));
+                }
+                res => res?,
+            };
+
+            restored
+                .map(|_| ())
+                .ok_or(TinyUrlError::IdNotFound(id.to_string()))
+        })
+        .await
+    }
+
+    async fn adjust_clicks(&self, id: &str, delta: i64, reset: bool) -> Result<i64, TinyUrlError> {
+        self.query("adjust_clicks", async {
+            let clicks: Option<i64> = db_query!(
+                "adjust_clicks",
+                sqlx::query_scalar(
+                    r#"
+                    UPDATE urls
+                    SET clicks = CASE WHEN $1::boolean THEN 0 ELSE GREATEST(clicks + $2, 0) END
+                    WHERE id = $3 AND deleted_at IS NULL
+                    RETURNING clicks
+                    "#,
+                )
+                .bind(reset)
+                .bind(delta)
+                .bind(id),
+                |query| query.fetch_optional(&self.db)
+            )
+            .await?;
+
+            clicks.ok_or(TinyUrlError::IdNotFound(id.to_string()))
+        })
+        .await
+    }
+
+    async fn purge_deleted(&self, older_than: Duration) -> Result<u64, TinyUrlError> {
+        self.query("purge_deleted", async {
+            let res = db_query!(
+                "purge_deleted",
+                sqlx::query(
+                    r#"
+                    DELETE FROM urls
+                    WHERE deleted_at IS NOT NULL AND deleted_at < NOW() - $1 * INTERVAL '1 second'
+                    "#,
+                )
+                .bind(older_than.as_secs_f64()),
+                |query| query.execute(&self.db)
+            )
+            .await?;
+
+            Ok(res.rows_affected())
+        })
+        .await
+    }
+
+    async fn get_stats(&self, id: &str, unlocked: bool) -> Result<UrlRecord, TinyUrlError> {
+        let row: Option<StatsRow> = self
+            .read("get_stats", |db| async move {
+                db_query!(
+                    "get_stats",
+                    sqlx::query_as(
+                        r#"
+                        SELECT id, url, clicks, created_at, tags, notes,
+                               password_hash IS NOT NULL AS password_protected
+                        FROM urls
+                        WHERE id = $1 AND deleted_at IS NULL AND reserved_until IS NULL
+                        "#,
+                    )
+                    .bind(id),
+                    |query| query.fetch_optional(&db)
+                )
+                .await
+            })
+            .await?;
+
+        match row {
+            Some(row) if row.password_protected && !unlocked => {
+                Err(TinyUrlError::PasswordRequired(id.to_string()))
+            }
+            Some(row) => Ok(row.record),
+            None => Err(TinyUrlError::IdNotFound(id.to_string())),
+        }
+    }
+
+    async fn global_stats(&self) -> Result<GlobalStats, TinyUrlError> {
+        // the totals are window aggregates over all live links, evaluated before the
+        // limit, so that every one of the top rows carries them
+        let top: Vec<TopUrl> = self
+            .read("global_stats", |db| async move {
+                db_query!(
+                    "global_stats",
+                    sqlx::query_as(
+                        r#"
+                        SELECT id, url, clicks, created_at, tags, notes,
+                               COUNT(*) OVER () AS total_urls,
+                               (SUM(clicks) OVER ())::BIGINT AS total_clicks,
+                               COUNT(*) FILTER (WHERE created_at > NOW() - INTERVAL '1 day')
+                                   OVER () AS urls_created_last_24h,
+                               COUNT(*) FILTER (WHERE expires_at <= NOW() + INTERVAL '1 day')
+                                   OVER () AS urls_expiring_next_24h
+                        FROM urls
+                        WHERE deleted_at IS NULL
+                          AND reserved_until IS NULL
+                          AND (expires_at IS NULL OR expires_at > NOW())
+                        ORDER BY clicks DESC, created_at DESC
+                        LIMIT 10
+                        "#,
+                    ),
+                    |query| query.fetch_all(&db)
+                )
+                .await
+            })
+            .await?;
+
+        let Some(first) = top.first() else {
+            return Ok(GlobalStats::default());
+        };
+
+        Ok(GlobalStats {
+            total_urls: first.total_urls,
+            total_clicks: first.total_clicks,
+            urls_created_last_24h: first.urls_created_last_24h,
+            urls_expiring_next_24h: first.urls_expiring_next_24h,
+            top_10_urls: top.into_iter().map(|row| row.record).collect(),
+        })
+    }
+
+    async fn count_by_host(&self) -> Result<Vec<DomainStats>, TinyUrlError> {
+        // stored urls are normalized, so the host follows the scheme and any userinfo in
+        // lowercase; IPv6 hosts keep their brackets
+        self.read("count_by_host", |db| async move {
+            let hosts = db_query!(
+                "count_by_host",
+                sqlx::query_as(
+                    r#"
+                    SELECT substring(
+                               url FROM '^[a-z][a-z0-9+.-]*://(?:[^@/]*@)?(\[[^]]*\]|[^/:?#]+)'
+                           ) AS domain,
+                           COUNT(*) AS urls,
+                           COALESCE(SUM(clicks), 0)::BIGINT AS clicks
+                    FROM urls
+                    WHERE deleted_at IS NULL AND reserved_until IS NULL
+                    GROUP BY 1
+                    "#,
+                ),
+                |query| query.fetch_all(&db)
+            )
+            .await?;
+
+            Ok(hosts)
+        })
+        .await
+    }
+
+    async fn search_urls(&self, keyword: &str, limit: u32) -> Result<Vec<SearchHit>, TinyUrlError> {
+        // `%`, `_` and `\` in the keyword are matched literally
+        let pattern = format!(
+            "%{}%",
+            keyword
+                .replace('\\', r"\\")
+                .replace('%', r"\%")
+                .replace('_', r"\_")
+        );
+
+        self.read("search_urls", |db| {
+            let pattern = pattern.clone();
+            async move {
+                let hits = db_query!(
+                    "search_urls",
+                    sqlx::query_as(
+                        r#"
+                        SELECT id, url, clicks
+                        FROM urls
+                        WHERE url ILIKE $1 AND deleted_at IS NULL AND reserved_until IS NULL
+                        ORDER BY created_at DESC, id DESC
+                        LIMIT $2
+                        "#,
+                    )
+                    .bind(pattern)
+                    .bind(i64::from(limit)),
+                    |query| query.fetch_all(&db)
+                )
+                .await?;
+
+                Ok(hits)
+            }
+        })
+        .await
+    }
+
+    async fn list_urls(
+        &self,
+        after: Option<&Cursor>,
+        limit: u32,
+        tag: Option<&str>,
+    ) -> Result<Vec<UrlRecord>, TinyUrlError> {
+        self.read("list_urls", |db| async move {
+            let urls = db_query!(
+                "list_urls",
+                sqlx::query_as(
+                    r#"
+                    SELECT id, url, clicks, created_at, tags, notes FROM urls
+                    WHERE deleted_at IS NULL
+                      AND reserved_until IS NULL
+                      AND ($2::text IS NULL OR tags @> ARRAY[$2])
+                      AND ($3::timestamptz IS NULL OR (created_at, id) < ($3, $4::text))
+                    ORDER BY created_at DESC, id DESC
+                    LIMIT $1
+                    "#,
+                )
+                .bind(i64::from(limit))
+                .bind(tag)
+                .bind(after.map(|cursor| cursor.created_at))
+                .bind(after.map(|cursor| cursor.id.as_str())),
+                |query| query.fetch_all(&db)
+            )
+            .await?;
+
+            Ok(urls)
+        })
+        .await
+    }
+
+    async fn list_deleted_urls(
+        &self,
+        page: u32,
+        per_page: u32,
+    ) -> Result<(Vec<UrlRecord>, i64), TinyUrlError> {
+        self.query("list_deleted_urls", async {
+            let offset = i64::from(page.saturating_sub(1)) * i64::from(per_page);
+
+            let urls = db_query!(
+                "list_deleted_urls",
+                sqlx::query_as(
+                    r#"
+                    SELECT id, url, clicks, created_at, deleted_at, tags, notes FROM urls
+                    WHERE deleted_at IS NOT NULL
+                    ORDER BY deleted_at DESC
+                    LIMIT $1 OFFSET $2
+                    "#,
+                )
+                .bind(i64::from(per_page))
+                .bind(offset),
+                |query| query.fetch_all(&self.db)
+            )
+            .await?;
+
+            let total = db_query!(
+                "list_deleted_urls",
+                sqlx::query_scalar(
+                    r#"
+                    SELECT COUNT(*) FROM urls WHERE deleted_at IS NOT NULL
+                    "#,
+                ),
+                |query| query.fetch_one(&self.db)
+            )
+            .await?;
+
+            Ok((urls, total))
+        })
+        .await
+    }
+
+    async fn record_audit(
+        &self,
+        action: AuditAction,
+        id: &str,
+        actor: &str,
+    ) -> Result<(), TinyUrlError> {
+        self.query("record_audit", async {
+            db_query!(
+                "record_audit",
+                sqlx::query(
+                    r#"
+                    INSERT INTO audit_log (action, url_id, actor) VALUES ($1, $2, $3)
+                    "#,
+                )
+                .bind(action)
+                .bind(id)
+                .bind(actor),
+                |query| query.execute(&self.db)
+            )
+            .await?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list_audit(
+        &self,
+        id: Option<&str>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        page: u32,
+        per_page: u32,
+    ) -> Result<(Vec<AuditEntry>, i64), TinyUrlError> {
+        self.query("list_audit", async {
+            let offset = i64::from(page.saturating_sub(1)) * i64::from(per_page);
+
+            let entries = db_query!(
+                "list_audit",
+                sqlx::query_as(
+                    r#"
+                    SELECT id, action, url_id, actor, timestamp FROM audit_log
+                    WHERE ($1::varchar IS NULL OR url_id = $1)
+                      AND ($2::timestamptz IS NULL OR timestamp >= $2)
+                      AND ($3::timestamptz IS NULL OR timestamp < $3)
+                    ORDER BY timestamp DESC, id DESC
+                    LIMIT $4 OFFSET $5
+                    "#,
+                )
+                .bind(id)
+                .bind(from)
+                .bind(to)
+                .bind(i64::from(per_page))
+                .bind(offset),
+                |query| query.fetch_all(&self.db)
+            )
+            .await?;
+
+            let total = db_query!(
+                "list_audit",
+                sqlx::query_scalar(
+                    r#"
+                    SELECT COUNT(*) FROM audit_log
+                    WHERE ($1::varchar IS NULL OR url_id = $1)
+                      AND ($2::timestamptz IS NULL OR timestamp >= $2)
+                      AND ($3::timestamptz IS NULL OR timestamp < $3)
+                    "#,
+                )
+                .bind(id)
+                .bind(from)
+                .bind(to),
+                |query| query.fetch_one(&self.db)
+            )
+            .await?;
+
+            Ok((entries, total))
+        })
+        .await
+    }
+
+    async fn import(&self, links: &[(String, String)]) -> Result<Vec<String>, TinyUrlError> {
+        self.query("import", async {
+            let mut tx = self.db.begin().await?;
+            let mut imported = Vec::new();
+
+            for (id, url) in links {
+                let res: Option<String> = db_query!(
+                    "import",
+                    sqlx::query_scalar(
+                        r#"
+                        INSERT INTO urls (id, url) VALUES ($1, $2)
+                        ON CONFLICT DO NOTHING
+                        RETURNING id
+                        "#,
+                    )
+                    .bind(id)
+                    .bind(url),
+                    |query| query.fetch_optional(&mut *tx)
+                )
+                .await?;
+
+                imported.extend(res);
+            }
+
+            tx.commit().await?;
+
+            Ok(imported)
+        })
+        .await
+    }
+
+    async fn bulk_insert(&self, links: &[(String, String)]) -> Result<u64, TinyUrlError> {
+        let copy = async {
+            let mut conn = self.db.acquire().await?;
+            // one statement, so a conflicting row aborts the whole `COPY`; dropping `copy`
+            // on an error aborts it as well
+            let mut copy = conn.copy_in_raw(COPY_URLS).await?;
+
+            let mut buf = COPY_HEADER.to_vec();
+            for (id, url) in links {
+                copy_row(&mut buf, &[id, url]);
+                if buf.len() >= COPY_CHUNK_SIZE {
+                    copy.send(buf.as_slice()).await?;
+                    buf.clear();
+                }
+            }
+            buf.extend_from_slice(COPY_TRAILER);
+            copy.send(buf).await?;
+
+            copy.finish().await.map_err(copy_error)
+        };
+        // `db_query!` only takes queries, so the span is set up by hand
+        #[cfg(debug_assertions)]
+        let copy = tracing::Instrument::instrument(
+            copy,
+            tracing::debug_span!("bulk_insert", sql = COPY_URLS, rows = links.len()),
+        );
+
+        self.query("bulk_insert", copy).await
+    }
+
+    fn export(&self) -> BoxStream<'static, Result<ExportRecord, TinyUrlError>> {
+        // the row stream borrows the pool, so it is driven by a task that owns a handle to it
+        let (tx, rx) = mpsc::channel(EXPORT_BUFFER);
+        let db = self.db.clone();
+
+        // a stream cannot be instrumented, so the span covers the task driving it
+        tokio::spawn(db_query!(
+            "export",
+            sqlx::query_as::<_, ExportRecord>(
+                r#"
+                SELECT id, url, clicks, created_at, expires_at, redirect_type, max_clicks,
+                       deleted_at, tags, notes
+                FROM urls
+                WHERE reserved_until IS NULL
+                ORDER BY created_at
+                "#,
+            ),
+            |query| async move { forward(query.fetch(&db), tx).await }
+        ));
+
+        received(rx)
+    }
+
+    fn sitemap(
+        &self,
+        page: u32,
+        per_page: u32,
+    ) -> BoxStream<'static, Result<(String, DateTime<Utc>), TinyUrlError>> {
+        let offset = i64::from(page.saturating_sub(1)) * i64::from(per_page);
+        let (tx, rx) = mpsc::channel(EXPORT_BUFFER);
+        let db = self.db.clone();
+
+        tokio::spawn(db_query!(
+            "sitemap",
+            sqlx::query_as::<_, (String, DateTime<Utc>)>(
+                r#"
+                SELECT id, created_at FROM urls
+                WHERE deleted_at IS NULL
+                  AND reserved_until IS NULL
+                  AND (expires_at IS NULL OR expires_at > NOW())
+                  AND password_hash IS NULL
+                  AND NOT private
+                ORDER BY created_at, id
+                LIMIT $1 OFFSET $2
+                "#,
+            )
+            .bind(i64::from(per_page))
+            .bind(offset),
+            // the row stream borrows the pool, like the one of `export`
+            |query| async move { forward(query.fetch(&db), tx).await }
+        ));
+
+        received(rx)
+    }
+
+    async fn create_webhook(
+        &self,
+        target_url: &str,
+        req: &WebhookRequest,
+    ) -> Result<Webhook, TinyUrlError> {
+        self.query("create_webhook", async {
+            let webhook = db_query!(
+                "create_webhook",
+                sqlx::query_as(
+                    r#"
+                    INSERT INTO webhooks (target_url, event, url_id) VALUES ($1, $2, $3)
+                    RETURNING id, target_url, event, url_id, created_at
+                    "#,
+                )
+                .bind(target_url)
+                .bind(req.event)
+                .bind(req.url_id.as_deref()),
+                |query| query.fetch_one(&self.db)
+            )
+            .await?;
+
+            Ok(webhook)
+        })
+        .await
+    }
+
+    async fn list_webhooks(&self, event: WebhookEvent) -> Result<Vec<Webhook>, TinyUrlError> {
+        self.query("list_webhooks", async {
+            let webhooks = db_query!(
+                "list_webhooks",
+                sqlx::query_as(
+                    r#"
+                    SELECT id, target_url, event, url_id, created_at FROM webhooks
+                    WHERE event = $1
+                    "#,
+                )
+                .bind(event),
+                |query| query.fetch_all(&self.db)
+            )
+            .await?;
+
+            Ok(webhooks)
+        })
+        .await
+    }
+
+    async fn api_key_limit(&self, key: &str) -> Result<Option<u64>, TinyUrlError> {
+        self.query("api_key_limit", async {
+            let limit: Option<i32> = db_query!(
+                "api_key_limit",
+                sqlx::query_scalar(
+                    r#"
+                    SELECT requests_per_minute FROM api_keys WHERE key = $1
+                    "#,
+                )
+                .bind(key),
+                |query| query.fetch_optional(&self.db)
+            )
+            .await?;
+
+            Ok(limit.map(|limit| limit.max(0) as u64))
+        })
+        .await
+    }
+
+    async fn ping(&self) -> Result<(), TinyUrlError> {
+        self.query("ping", async {
+            db_query!("ping", sqlx::query("SELECT 1"), |query| {
+                query.execute(&self.db)
+            })
+            .await
+            .map_err(TinyUrlError::HealthCheckFailed)?;
+
+            Ok(())
+        })
+        .await
+    }
+}
@@ -0,0 +1,1666 @@
+use std::{collections::HashMap, future::Future, str::FromStr, time::Duration};
+
+use axum::async_trait;
+use chrono::{DateTime, TimeDelta, Utc};
+use futures::{stream::BoxStream, StreamExt};
+use metrics::counter;
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    types::Json,
+    FromRow, SqlitePool,
+};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+use url::Url;
+use uuid::Uuid;
+
+use super::{forward, received, timed_query, UrlStore};
+use crate::{
+    redact_password, AuditAction, AuditEntry, Click, ClickBucket, ClickInfo, Config, Cursor,
+    DomainStats, ExportRecord, GlobalStats, Granularity, RedirectTarget, RedirectType, SearchHit,
+    ShortenRequest, TinyUrlError, UpdateRequest, UrlRecord, Webhook, WebhookEvent, WebhookRequest,
+};
+
+/// Rows buffered between the export query and a slow client.
+const EXPORT_BUFFER: usize = 256;
+
+/// A time as stored in the timestamp columns, the format `strftime('%Y-%m-%d %H:%M:%f')`
+/// writes, so that comparing them as text compares them in time.
+fn timestamp(at: DateTime<Utc>) -> String {
+    at.format("%Y-%m-%d %H:%M:%S%.3f").to_string()
+}
+
+/// The stored time `age` ago, or `None` if that is before the earliest time there can be.
+fn ago(age: Duration) -> Option<String> {
+    TimeDelta::from_std(age)
+        .ok()
+        .and_then(|age| Utc::now().checked_sub_signed(age))
+        .map(timestamp)
+}
+
+/// The `strftime` format truncating a stored time to the start of its `granularity` bucket.
+fn bucket_format(granularity: Granularity) -> &'static str {
+    match granularity {
+        Granularity::Hour => "%Y-%m-%d %H:00:00.000",
+        Granularity::Day => "%Y-%m-%d 00:00:00.000",
+    }
+}
+
+/// Reports an insert that broke a unique index as the conflict the other stores report.
+fn insert_error(e: sqlx::Error, id: &str, url: &str) -> TinyUrlError {
+    match &e {
+        // the message names the columns of the index: `UNIQUE constraint failed: urls.id`
+        sqlx::Error::Database(db_error) if db_error.is_unique_violation() => {
+            if db_error.message().ends_with("urls.id") {
+                TinyUrlError::CodeAlreadyTaken(id.to_string())
+            } else {
+                TinyUrlError::UrlAlreadyExists(url.to_string())
+            }
+        }
+        _ => e.into(),
+    }
+}
+
+/// A `UrlRecord` with its tags stored as a JSON array.
+#[derive(Debug, FromRow)]
+struct LinkRow {
+    id: String,
+    url: String,
+    clicks: i64,
+    created_at: DateTime<Utc>,
+    deleted_at: Option<DateTime<Utc>>,
+    tags: Json<Vec<String>>,
+    notes: Option<String>,
+}
+
+impl From<LinkRow> for UrlRecord {
+    fn from(row: LinkRow) -> Self {
+        UrlRecord {
+            id: row.id,
+            url: row.url,
+            clicks: row.clicks,
+            created_at: row.created_at,
+            deleted_at: row.deleted_at,
+            tags: row.tags.0,
+            notes: row.notes,
+        }
+    }
+}
+
+/// An `ExportRecord` with its tags stored as a JSON array.
+#[derive(Debug, FromRow)]
+struct ExportRow {
+    id: String,
+    url: String,
+    clicks: i64,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+    redirect_type: RedirectType,
+    max_clicks: Option<i32>,
+    deleted_at: Option<DateTime<Utc>>,
+    tags: Json<Vec<String>>,
+    notes: Option<String>,
+}
+
+impl From<ExportRow> for ExportRecord {
+    fn from(row: ExportRow) -> Self {
+        ExportRecord {
+            id: row.id,
+            url: row.url,
+            clicks: row.clicks,
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+            redirect_type: row.redirect_type,
+            max_clicks: row.max_clicks,
+            deleted_at: row.deleted_at,
+            tags: row.tags.0,
+            notes: row.notes,
+        }
+    }
+}
+
+/// A `RedirectTarget` with its allowed referers stored as a JSON array.
+#[derive(Debug, FromRow)]
+struct TargetRow {
+    url: String,
+    redirect_type: RedirectType,
+    expires_at: Option<DateTime<Utc>>,
+    max_clicks: Option<i32>,
+    created_at: DateTime<Utc>,
+    allowed_referers: Json<Vec<String>>,
+    password_protected: bool,
+}
+
+impl From<TargetRow> for RedirectTarget {
+    fn from(row: TargetRow) -> Self {
+        RedirectTarget {
+            url: row.url,
+            redirect_type: row.redirect_type,
+            expires_at: row.expires_at,
+            max_clicks: row.max_clicks,
+            created_at: Some(row.created_at),
+            allowed_referers: row.allowed_referers.0,
+            password_protected: row.password_protected,
+        }
+    }
+}
+
+/// One of the most clicked links, with the totals of `global_stats` alongside.
+#[derive(Debug, FromRow)]
+struct TopUrl {
+    #[sqlx(flatten)]
+    link: LinkRow,
+    total_urls: i64,
+    total_clicks: i64,
+    urls_created_last_24h: i64,
+    urls_expiring_next_24h: i64,
+}
+
+/// A link's record and whether a password protects it, as read by `get_stats`.
+#[derive(Debug, FromRow)]
+struct StatsRow {
+    #[sqlx(flatten)]
+    link: LinkRow,
+    password_protected: bool,
+}
+
+/// Store on a single SQLite database file, for deployments that do not run a Postgres.
+///
+/// There is no read replica, so `TINYURL_DB_READ_ADDR` is ignored and every query runs on
+/// the one pool.
+#[derive(Debug, Clone)]
+pub struct SqliteStore {
+    db: SqlitePool,
+    slow_query_threshold: Duration,
+}
+
+impl SqliteStore {
+    /// Opens the database at `DATABASE_URL`, creating the file if it does not exist, and
+    /// applies pending migrations from `migrations_sqlite/`.
+    pub async fn connect(config: &Config) -> Result<Self, TinyUrlError> {
+        let options = SqliteConnectOptions::from_str(&config.database_url)?.create_if_missing(true);
+        let db = SqlitePoolOptions::new()
+            .max_connections(config.db_max_connections)
+            .acquire_timeout(config.db_connect_timeout)
+            .connect_with(options)
+            .await?;
+        info!(
+            "Connected to database: {} (max connections: {}, acquire timeout: {:?})",
+            redact_password(&config.database_url),
+            config.db_max_connections,
+            config.db_connect_timeout
+        );
+        if config.db_read_url.is_some() {
+            warn!("TINYURL_DB_READ_ADDR is ignored by SQLite, reading from the database file");
+        }
+
+        sqlx::migrate!("./migrations_sqlite").run(&db).await?;
+
+        Ok(Self {
+            db,
+            slow_query_threshold: config.slow_query_threshold,
+        })
+    }
+
+    pub async fn close(&self) {
+        self.db.close().await;
+    }
+
+    /// Awaits the store operation `f` named `name`, timed by `timed_query`.
+    ///
+    /// Requests queue for a connection here, since they hold one only briefly; one that is
+    /// still waiting after the acquire timeout fails with `PoolExhausted`.
+    async fn query<T>(
+        &self,
+        name: &str,
+        f: impl Future<Output = Result<T, TinyUrlError>>,
+    ) -> Result<T, TinyUrlError> {
+        match timed_query(name, self.slow_query_threshold, f).await {
+            Err(TinyUrlError::DatabaseError(sqlx::Error::PoolTimedOut)) => {
+                counter!("pool_exhaustion_total").increment(1);
+                Err(TinyUrlError::PoolExhausted)
+            }
+            res => res,
+        }
+    }
+
+    /// Tells a link that used up its clicks apart from one that never existed.
+    async fn missing(&self, id: &str, referer: Option<&str>) -> Result<TinyUrlError, TinyUrlError> {
+        let row: Option<(bool, bool, bool, bool)> = db_query!(
+            "missing",
+            sqlx::query_as(
+                r#"
+                SELECT
+                    COALESCE(clicks >= max_clicks, FALSE),
+                    deleted_at IS NULL
+                      AND reserved_until IS NULL
+                      AND (expires_at IS NULL OR expires_at > ?3),
+                    allowed_referers = '[]'
+                      OR EXISTS (SELECT 1 FROM json_each(allowed_referers) WHERE value = ?2),
+                    password_hash IS NOT NULL
+                FROM urls WHERE id = ?1
+                "#,
+            )
+            .bind(id)
+            .bind(referer)
+            .bind(timestamp(Utc::now())),
+            |query| query.fetch_optional(&self.db)
+        )
+        .await?;
+
+        let err = match row {
+            Some((true, _, _, _)) => TinyUrlError::LinkExpired(id.to_string()),
+            Some((_, true, false, _)) => TinyUrlError::RefererNotAllowed(id.to_string()),
+            Some((_, true, true, true)) => TinyUrlError::PasswordRequired(id.to_string()),
+            _ => TinyUrlError::IdNotFound(id.to_string()),
+        };
+        Ok(err)
+    }
+}
+
+#[async_trait]
+impl UrlStore for SqliteStore {
+    async fn shorten(
+        &self,
+        id: &str,
+        url: &str,
+        req: &ShortenRequest,
+    ) -> Result<(String, bool), TinyUrlError> {
+        self.query("shorten", async {
+            let now = Utc::now();
+            let expires_at = req
+                .ttl_seconds_i64()
+                .and_then(TimeDelta::try_seconds)
+                .and_then(|ttl| now.checked_add_signed(ttl))
+                .map(timestamp);
+
+            // Only plain links are reused, the ones `urls_url_live_key` covers, so the insert
+            // is skipped when the url already has one. Writes are serialized, so a link that
+            // is not inserted either took the code first or is the one to reuse.
+            let inserted: Option<String> = db_query!(
+                "shorten",
+                sqlx::query_scalar(
+                    r#"
+                    INSERT INTO urls (
+                        id, url, expires_at, redirect_type, max_clicks, tags, notes,
+                        allowed_referers, password_hash, private
+                    )
+                    SELECT ?1, ?2, ?3, ?4, ?5, ?6, NULLIF(?7, ''), ?8, ?9, ?10
+                    WHERE NOT (?11 AND EXISTS (
+                        SELECT 1 FROM urls
+                        WHERE url = ?2
+                          AND redirect_type = ?4
+                          AND deleted_at IS NULL
+                          AND reserved_until IS NULL
+                          AND expires_at IS NULL
+                          AND max_clicks IS NULL
+                          AND password_hash IS NULL
+                          AND allowed_referers = '[]'
+                          AND NOT private
+                    ))
+                    ON CONFLICT DO NOTHING
+                    RETURNING id
+                    "#,
+                )
+                .bind(id)
+                .bind(url)
+                .bind(expires_at)
+                .bind(req.redirect_type)
+                .bind(req.max_clicks_i32())
+                .bind(Json(req.tags.as_deref().unwrap_or_default()))
+                .bind(req.notes.as_deref())
+                .bind(Json(req.referer_origins()))
+                .bind(req.password_hash.as_deref())
+                .bind(req.private)
+                .bind(req.reusable()),
+                |query| query.fetch_optional(&self.db)
+            )
+            .await?;
+
+            if let Some(inserted) = inserted {
+                return Ok((inserted, true));
+            }
+            if !req.reusable() {
+                return Err(TinyUrlError::CodeAlreadyTaken(id.to_string()));
+            }
+
+            let existing: Option<String> = db_query!(
+                "shorten",
+                sqlx::query_scalar(
+                    r#"
+                    SELECT id FROM urls
+                    WHERE url = ?1
+                      AND redirect_type = ?2
+                      AND deleted_at IS NULL
+                      AND reserved_until IS NULL
+                      AND expires_at IS NULL
+                      AND max_clicks IS NULL
+                      AND password_hash IS NULL
+                      AND allowed_referers = '[]'
+                      AND NOT private
+                    "#,
+                )
+                .bind(url)
+                .bind(req.redirect_type),
+                |query| query.fetch_optional(&self.db)
+            )
+            .await?;
+
+            existing
+                .map(|existing| (existing, false))
+                .ok_or(TinyUrlError::CodeAlreadyTaken(id.to_string()))
+        })
+        .await
+    }
+
+    async fn reserve(&self, id: &str, until: DateTime<Utc>) -> Result<(), TinyUrlError> {
+        self.query("reserve", async {
+            let res: Option<String> = db_query!(
+                "reserve",
+                sqlx::query_scalar(
+                    r#"
+                    INSERT INTO urls (id, url, reserved_until) VALUES (?1, '', ?2)
+                    ON CONFLICT DO NOTHING
+                    RETURNING id
+                    "#,
+                )
+                .bind(id)
+                .bind(timestamp(until)),
+                |query| query.fetch_optional(&self.db)
+            )
+            .await?;
+
+            res.map(|_| ())
+                .ok_or(TinyUrlError::CodeAlreadyTaken(id.to_string()))
+        })
+        .await
+    }
+
+    async fn delete_expired(&self) -> Result<u64, TinyUrlError> {
+        self.query("delete_expired", async {
+            let res = db_query!(
+                "delete_expired",
+                sqlx::query(
+                    r#"
+                    DELETE FROM urls
+                    WHERE (expires_at IS NOT NULL AND expires_at < ?1)
+                       OR (max_clicks IS NOT NULL AND clicks >= max_clicks)
+                    "#,
+                )
+                .bind(timestamp(Utc::now())),
+                |query| query.execute(&self.db)
+            )
+            .await?;
+
+            Ok(res.rows_affected())
+        })
+        .await
+    }
+
+    async fn release_expired_reservations(&self) -> Result<u64, TinyUrlError> {
+        self.query("release_expired_reservations", async {
+            let res = db_query!(
+                "release_expired_reservations",
+                sqlx::query(
+                    r#"
+                    DELETE FROM urls WHERE reserved_until IS NOT NULL AND reserved_until < ?1
+                    "#,
+                )
+                .bind(timestamp(Utc::now())),
+                |query| query.execute(&self.db)
+            )
+            .await?;
+
+            Ok(res.rows_affected())
+        })
+        .await
+    }
+
+    async fn get_url_by_id(
+        &self,
+        id: &str,
+        referer: Option<&str>,
+        unlocked: bool,
+    ) -> Result<RedirectTarget, TinyUrlError> {
+        self.query("get_url_by_id", async {
+            // count the click in the same statement that resolves the url, retiring the link
+            // once its last allowed click is used, and leaving used-up links that were
+            // restored or adjusted expired
+            let target: Option<TargetRow> = db_query!(
+                "get_url_by_id",
+                sqlx::query_as(
+                    r#"
+                    UPDATE urls SET
+                        clicks = clicks + 1,
+                        deleted_at = CASE WHEN clicks + 1 >= max_clicks THEN ?4 END
+                    WHERE id = ?1
+                      AND deleted_at IS NULL
+                      AND reserved_until IS NULL
+                      AND (expires_at IS NULL OR expires_at > ?4)
+                      AND (max_clicks IS NULL OR clicks < max_clicks)
+                      AND (
+                          allowed_referers = '[]'
+                          OR EXISTS (SELECT 1 FROM json_each(allowed_referers) WHERE value = ?2)
+                      )
+                      AND (?3 OR password_hash IS NULL)
+                    RETURNING
+                        url, redirect_type, expires_at, max_clicks, created_at, allowed_referers,
+                        password_hash IS NOT NULL AS password_protected
+                    "#,
+                )
+                .bind(id)
+                .bind(referer)
+                .bind(unlocked)
+                .bind(timestamp(Utc::now())),
+                |query| query.fetch_optional(&self.db)
+            )
+            .await?;
+
+            match target {
+                Some(target) => Ok(target.into()),
+                None => Err(self.missing(id, referer).await?),
+            }
+        })
+        .await
+    }
+
+    async fn password_hash(&self, id: &str) -> Result<Option<String>, TinyUrlError> {
+        self.query("password_hash", async {
+            let hash: Option<Option<String>> = db_query!(
+                "password_hash",
+                sqlx::query_scalar(
+                    r#"
+                    SELECT password_hash FROM urls
+                    WHERE id = ?1
+                      AND deleted_at IS NULL
+                      AND reserved_until IS NULL
+                      AND (expires_at IS NULL OR expires_at > ?2)
+                    "#,
+                )
+                .bind(id)
+                .bind(timestamp(Utc::now())),
+                |query| query.fetch_optional(&self.db)
+            )
+            .await?;
+
+            hash.ok_or(TinyUrlError::IdNotFound(id.to_string()))
+        })
+        .await
+    }
+
+    async fn peek_url(&self, id: &str, referer: Option<&str>) -> Result<String, TinyUrlError> {
+        self.query("peek_url", async {
+            let url: Option<(String, bool, bool)> = db_query!(
+                "peek_url",
+                sqlx::query_as(
+                    r#"
+                    SELECT
+                        url,
+                        allowed_referers = '[]'
+                          OR EXISTS (SELECT 1 FROM json_each(allowed_referers) WHERE value = ?2),
+                        password_hash IS NOT NULL
+                    FROM urls
+                    WHERE id = ?1
+                      AND deleted_at IS NULL
+                      AND reserved_until IS NULL
+                      AND (expires_at IS NULL OR expires_at > ?3)
+                    "#,
+                )
+                .bind(id)
+                .bind(referer)
+                .bind(timestamp(Utc::now())),
+                |query| query.fetch_optional(&self.db)
+            )
+            .await?;
+
+            match url {
+                Some((_, false, _)) => Err(TinyUrlError::RefererNotAllowed(id.to_string())),
+                Some((url, true, false)) => Ok(url),
+                Some((_, true, true)) => Err(TinyUrlError::PasswordRequired(id.to_string())),
+                None => Err(TinyUrlError::IdNotFound(id.to_string())),
+            }
+        })
+        .await
+    }
+
+    async fn find_by_url(&self, url: &str) -> Result<String, TinyUrlError> {
+        self.query("find_by_url", async {
+            let id = db_query!(
+                "find_by_url",
+                sqlx::query_scalar(
+                    r#"
+                    SELECT id FROM urls
+                    WHERE url = ?1
+                      AND deleted_at IS NULL
+                      AND reserved_until IS NULL
+                      AND expires_at IS NULL
+                      AND max_clicks IS NULL
+                      AND password_hash IS NULL
+                      AND allowed_referers = '[]'
+                      AND NOT private
+                    ORDER BY created_at
+                    LIMIT 1
+                    "#,
+                )
+                .bind(url),
+                |query| query.fetch_optional(&self.db)
+            )
+            .await?;
+
+            id.ok_or(TinyUrlError::IdNotFound(url.to_string()))
+        })
+        .await
+    }
+
+    async fn count_click(&self, id: &str) -> Result<(), TinyUrlError> {
+        self.query("count_click", async {
+            db_query!(
+                "count_click",
+                sqlx::query(
+                    r#"
+                    UPDATE urls SET clicks = clicks + 1 WHERE id = ?1
+                    "#,
+                )
+                .bind(id),
+                |query| query.execute(&self.db)
+            )
+            .await?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn record_click(&self, id: &str, info: &ClickInfo) -> Result<(), TinyUrlError> {
+        self.query("record_click", async {
+            db_query!(
+                "record_click",
+                sqlx::query(
+                    r#"
+                    INSERT INTO clicks (url_id, ip_address, user_agent, referer)
+                    VALUES (?1, ?2, ?3, ?4)
+                    "#,
+                )
+                .bind(id)
+                .bind(info.ip.to_string())
+                .bind(&info.user_agent)
+                .bind(&info.referer),
+                |query| query.execute(&self.db)
+            )
+            .await?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list_clicks(
+        &self,
+        id: &str,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<Vec<Click>, TinyUrlError> {
+        self.query("list_clicks", async {
+            let clicks = db_query!(
+                "list_clicks",
+                sqlx::query_as(
+                    r#"
+                    SELECT clicked_at, ip_address, user_agent, referer FROM clicks
+                    WHERE url_id = ?1
+                      AND (?2 IS NULL OR clicked_at >= ?2)
+                      AND (?3 IS NULL OR clicked_at < ?3)
+                    ORDER BY clicked_at DESC
+                    "#,
+                )
+                .bind(id)
+                .bind(from.map(timestamp))
+                .bind(to.map(timestamp)),
+                |query| query.fetch_all(&self.db)
+            )
+            .await?;
+
+            Ok(clicks)
+        })
+        .await
+    }
+
+    async fn click_timeseries(
+        &self,
+        id: &str,
+        granularity: Granularity,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<ClickBucket>, TinyUrlError> {
+        self.query("click_timeseries", async {
+            let buckets = db_query!(
+                "click_timeseries",
+                sqlx::query_as(
+                    r#"
+                    SELECT strftime(?2, clicked_at) AS bucket, COUNT(*) AS clicks
+                    FROM clicks
+                    WHERE url_id = ?1 AND clicked_at >= ?3 AND clicked_at < ?4
+                    GROUP BY bucket
+                    ORDER BY bucket
+                    "#,
+                )
+                .bind(id)
+                .bind(bucket_format(granularity))
+                .bind(timestamp(from))
+                .bind(timestamp(to)),
+                |query| query.fetch_all(&self.db)
+            )
+            .await?;
+
+            Ok(buckets)
+        })
+        .await
+    }
+
+    async fn get_idempotency_key(
+        &self,
+        key: Uuid,
+        ttl: Duration,
+    ) -> Result<Option<(String, Option<String>)>, TinyUrlError> {
+        self.query("get_idempotency_key", async {
+            let entry = db_query!(
+                "get_idempotency_key",
+                sqlx::query_as(
+                    r#"
+                    SELECT request, url_id FROM idempotency_keys
+                    WHERE key = ?1 AND (?2 IS NULL OR created_at > ?2)
+                    "#,
+                )
+                .bind(key.to_string())
+                .bind(ago(ttl)),
+                |query| query.fetch_optional(&self.db)
+            )
+            .await?;
+
+            Ok(entry)
+        })
+        .await
+    }
+
+    async fn claim_idempotency_key(
+        &self,
+        key: Uuid,
+        request: &str,
+        ttl: Duration,
+    ) -> Result<bool, TinyUrlError> {
+        self.query("claim_idempotency_key", async {
+            let res = db_query!(
+                "claim_idempotency_key",
+                sqlx::query(
+                    r#"
+                    INSERT INTO idempotency_keys (key, request, created_at) VALUES (?1, ?2, ?3)
+                    ON CONFLICT (key) DO UPDATE
+                    SET request = excluded.request,
+                        url_id = NULL,
+                        created_at = excluded.created_at
+                    WHERE ?4 IS NOT NULL AND idempotency_keys.created_at <= ?4
+                    "#,
+                )
+                .bind(key.to_string())
+                .bind(request)
+                .bind(timestamp(Utc::now()))
+                .bind(ago(ttl)),
+                |query| query.execute(&self.db)
+            )
+            .await?;
+
+            Ok(res.rows_affected() == 1)
+        })
+        .await
+    }
+
+    async fn save_idempotency_key(&self, key: Uuid, id: &str) -> Result<(), TinyUrlError> {
+        self.query("save_idempotency_key", async {
+            db_query!(
+                "save_idempotency_key",
+                sqlx::query(
+                    r#"
+                    UPDATE idempotency_keys SET url_id = ?2 WHERE key = ?1
+                    "#,
+                )
+                .bind(key.to_string())
+                .bind(id),
+                |query| query.execute(&self.db)
+            )
+            .await?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn release_idempotency_key(&self, key: Uuid) -> Result<(), TinyUrlError> {
+        self.query("release_idempotency_key", async {
+            db_query!(
+                "release_idempotency_key",
+                sqlx::query(
+                    r#"
+                    DELETE FROM idempotency_keys WHERE key = ?1 AND url_id IS NULL
+                    "#,
+                )
+                .bind(key.to_string()),
+                |query| query.execute(&self.db)
+            )
+            .await?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn purge_idempotency_keys(&self, ttl: Duration) -> Result<(), TinyUrlError> {
+        self.query("purge_idempotency_keys", async {
+            let Some(cutoff) = ago(ttl) else {
+                return Ok(());
+            };
+
+            db_query!(
+                "purge_idempotency_keys",
+                sqlx::query(
+                    r#"
+                    DELETE FROM idempotency_keys WHERE created_at < ?1
+                    "#,
+                )
+                .bind(cutoff),
+                |query| query.execute(&self.db)
+            )
+            .await?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn update_url(
+        &self,
+        id: &str,
+        url: Option<&str>,
+        req: &UpdateRequest,
+    ) -> Result<(), TinyUrlError> {
+        self.query("update_url", async {
+            let res = db_query!(
+                "update_url",
+                sqlx::query(
+                    r#"
+                    UPDATE urls SET
+                        url = COALESCE(?1, url),
+                        reserved_until = CASE WHEN ?1 IS NULL THEN reserved_until END,
+                        tags = COALESCE(?3, tags),
+                        notes = CASE WHEN ?4 IS NULL THEN notes ELSE NULLIF(?4, '') END
+                    WHERE id = ?2
+                      AND deleted_at IS NULL
+                      AND (reserved_until IS NULL OR reserved_until > ?5)
+                    "#,
+                )
+                .bind(url)
+                .bind(id)
+                .bind(req.tags.as_deref().map(Json))
+                .bind(req.notes.as_deref())
+                .bind(timestamp(Utc::now())),
+                |query| query.execute(&self.db)
+            )
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::Database(e) if e.is_unique_violation() => {
+                    TinyUrlError::UrlAlreadyExists(url.unwrap_or_default().to_string())
+                }
+                e => e.into(),
+            })?;
+
+            if res.rows_affected() == 0 {
+                return Err(TinyUrlError::IdNotFound(id.to_string()));
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_url(&self, id: &str) -> Result<(), TinyUrlError> {
+        self.query("delete_url", async {
+            let deleted: Option<String> = db_query!(
+                "delete_url",
+                sqlx::query_scalar(
+                    r#"
+                    UPDATE urls SET deleted_at = ?2
+                    WHERE id = ?1 AND deleted_at IS NULL
+                    RETURNING id
+                    "#,
+                )
+                .bind(id)
+                .bind(timestamp(Utc::now())),
+                |query| query.fetch_optional(&self.db)
+            )
+            .await?;
+
+            deleted
+                .map(|_| ())
+                .ok_or(TinyUrlError::IdNotFound(id.to_string()))
+        })
+        .await
+    }
+
+    async fn restore_url(&self, id: &str) -> Result<(), TinyUrlError> {
+        self.query("restore_url", async {
+            let restored: Option<String> = match db_query!(
+                "restore_url",
+                sqlx::query_scalar(
+                    r#"
+                    UPDATE urls SET deleted_at = NULL
+                    WHERE id = ?1 AND deleted_at IS NOT NULL
+                    RETURNING id
+                    "#,
+                )
+                .bind(id),
+                |query| query.fetch_optional(&self.db)
+            )
+            .await
+            {
+                Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+                    let url = db_query!(
+                        "restore_url",
+                        sqlx::query_scalar("SELECT url FROM urls WHERE id = ?1").bind(id),
+                        |query| query.fetch_one(&self.db)
+                    )
+                    .await?;
+                    return Err(TinyUrlError::UrlAlreadyExists(url));
+                }
+                res => res?,
+            };
+
+            restored
+                .map(|_| ())
+                .ok_or(TinyUrlError::IdNotFound(id.to_string()))
+        })
+        .await
+    }
+
+    async fn adjust_clicks(&self, id: &str, delta: i64, reset: bool) -> Result<i64, TinyUrlError> {
+        self.query("adjust_clicks", async {
+            let clicks: Option<i64> = db_query!(
+                "adjust_clicks",
+                sqlx::query_scalar(
+                    r#"
+                    UPDATE urls
+                    SET clicks = CASE WHEN ?1 THEN 0 ELSE MAX(clicks + ?2, 0) END
+                    WHERE id = ?3 AND deleted_at IS NULL
+                    RETURNING clicks
+                    "#,
+                )
+                .bind(reset)
+                .bind(delta)
+                .bind(id),
+                |query| query.fetch_optional(&self.db)
+            )
+            .await?;
+
+            clicks.ok_or(TinyUrlError::IdNotFound(id.to_string()))
+        })
+        .await
+    }
+
+    async fn purge_deleted(&self, older_than: Duration) -> Result<u64, TinyUrlError> {
+        self.query("purge_deleted", async {
+            let Some(cutoff) = ago(older_than) else {
+                return Ok(0);
+            };
+
+            let res = db_query!(
+                "purge_deleted",
+                sqlx::query(
+                    r#"
+                    DELETE FROM urls WHERE deleted_at IS NOT NULL AND deleted_at < ?1
+                    "#,
+                )
+                .bind(cutoff),
+                |query| query.execute(&self.db)
+            )
+            .await?;
+
+            Ok(res.rows_affected())
+        })
+        .await
+    }
+
+    async fn get_stats(&self, id: &str, unlocked: bool) -> Result<UrlRecord, TinyUrlError> {
+        self.query("get_stats", async {
+            let row: Option<StatsRow> = db_query!(
+                "get_stats",
+                sqlx::query_as(
+                    r#"
+                    SELECT id, url, clicks, created_at, deleted_at, tags, notes,
+                           password_hash IS NOT NULL AS password_protected
+                    FROM urls
+                    WHERE id = ?1 AND deleted_at IS NULL AND reserved_until IS NULL
+                    "#,
+                )
+                .bind(id),
+                |query| query.fetch_optional(&self.db)
+            )
+            .await?;
+
+            match row {
+                Some(row) if row.password_protected && !unlocked => {
+                    Err(TinyUrlError::PasswordRequired(id.to_string()))
+                }
+                Some(row) => Ok(row.link.into()),
+                None => Err(TinyUrlError::IdNotFound(id.to_string())),
+            }
+        })
+        .await
+    }
+
+    async fn global_stats(&self) -> Result<GlobalStats, TinyUrlError> {
+        self.query("global_stats", async {
+            let now = Utc::now();
+
+            // the totals are window aggregates over all live links, evaluated before the
+            // limit, so that every one of the top rows carries them
+            let top: Vec<TopUrl> = db_query!(
+                "global_stats",
+                sqlx::query_as(
+                    r#"
+                    SELECT id, url, clicks, created_at, deleted_at, tags, notes,
+                           COUNT(*) OVER () AS total_urls,
+                           SUM(clicks) OVER () AS total_clicks,
+                           COUNT(*) FILTER (WHERE created_at > ?2) OVER () AS urls_created_last_24h,
+                           COUNT(*) FILTER (WHERE expires_at <= ?3) OVER () AS urls_expiring_next_24h
+                    FROM urls
+                    WHERE deleted_at IS NULL
+                      AND reserved_until IS NULL
+                      AND (expires_at IS NULL OR expires_at > ?1)
+                    ORDER BY clicks DESC, created_at DESC
+                    LIMIT 10
+                    "#,
+                )
+                .bind(timestamp(now))
+                .bind(timestamp(now - TimeDelta::days(1)))
+                .bind(timestamp(now + TimeDelta::days(1))),
+                |query| query.fetch_all(&self.db)
+            )
+            .await?;
+
+            let Some(first) = top.first() else {
+                return Ok(GlobalStats::default());
+            };
+
+            Ok(GlobalStats {
+                total_urls: first.total_urls,
+                total_clicks: first.total_clicks,
+                urls_created_last_24h: first.urls_created_last_24h,
+                urls_expiring_next_24h: first.urls_expiring_next_24h,
+                top_10_urls: top.into_iter().map(|row| row.link.into()).collect(),
+            })
+        })
+        .await
+    }
+
+    async fn count_by_host(&self) -> Result<Vec<DomainStats>, TinyUrlError> {
+        self.query("count_by_host", async {
+            // SQLite has no regular expressions to take the host out, so the links are
+            // grouped here, like `InMemoryStore` does
+            let links: Vec<(String, i64)> = db_query!(
+                "count_by_host",
+                sqlx::query_as(
+                    r#"
+                    SELECT url, clicks FROM urls
+                    WHERE deleted_at IS NULL AND reserved_until IS NULL
+                    "#,
+                ),
+                |query| query.fetch_all(&self.db)
+            )
+            .await?;
+
+            let mut hosts: HashMap<String, DomainStats> = HashMap::new();
+            for (url, clicks) in links {
+                let url = Url::parse(&url).expect("stored urls are valid");
+                let host = url.host_str().unwrap_or_default().to_string();
+
+                let stats = hosts.entry(host.clone()).or_insert(DomainStats {
+                    domain: host,
+                    urls: 0,
+                    clicks: 0,
+                });
+                stats.urls += 1;
+                stats.clicks += clicks;
+            }
+
+            Ok(hosts.into_values().collect())
+        })
+        .await
+    }
+
+    async fn search_urls(&self, keyword: &str, limit: u32) -> Result<Vec<SearchHit>, TinyUrlError> {
+        // `%`, `_` and `\` in the keyword are matched literally; `LIKE` ignores the case of
+        // ASCII letters only
+        let pattern = format!(
+            "%{}%",
+            keyword
+                .replace('\\', r"\\")
+                .replace('%', r"\%")
+                .replace('_', r"\_")
+        );
+
+        self.query("search_urls", async {
+            let hits = db_query!(
+                "search_urls",
+                sqlx::query_as(
+                    r#"
+                    SELECT id, url, clicks
+                    FROM urls
+                    WHERE url LIKE ?1 ESCAPE '\' AND deleted_at IS NULL AND reserved_until IS NULL
+                    ORDER BY created_at DESC, id DESC
+                    LIMIT ?2
+                    "#,
+                )
+                .bind(pattern)
+                .bind(i64::from(limit)),
+                |query| query.fetch_all(&self.db)
+            )
+            .await?;
+
+            Ok(hits)
+        })
+        .await
+    }
+
+    async fn list_urls(
+        &self,
+        after: Option<&Cursor>,
+        limit: u32,
+        tag: Option<&str>,
+    ) -> Result<Vec<UrlRecord>, TinyUrlError> {
+        self.query("list_urls", async {
+            let urls: Vec<LinkRow> = db_query!(
+                "list_urls",
+                sqlx::query_as(
+                    r#"
+                    SELECT id, url, clicks, created_at, deleted_at, tags, notes FROM urls
+                    WHERE deleted_at IS NULL
+                      AND reserved_until IS NULL
+                      AND (?2 IS NULL OR EXISTS (SELECT 1 FROM json_each(tags) WHERE value = ?2))
+                      AND (?3 IS NULL OR (created_at, id) < (?3, ?4))
+                    ORDER BY created_at DESC, id DESC
+                    LIMIT ?1
+                    "#,
+                )
+                .bind(i64::from(limit))
+                .bind(tag)
+                .bind(after.map(|cursor| timestamp(cursor.created_at)))
+                .bind(after.map(|cursor| cursor.id.as_str())),
+                |query| query.fetch_all(&self.db)
+            )
+            .await?;
+
+            Ok(urls.into_iter().map(UrlRecord::from).collect())
+        })
+        .await
+    }
+
+    async fn list_deleted_urls(
+        &self,
+        page: u32,
+        per_page: u32,
+    ) -> Result<(Vec<UrlRecord>, i64), TinyUrlError> {
+        self.query("list_deleted_urls", async {
+            let offset = i64::from(page.saturating_sub(1)) * i64::from(per_page);
+
+            let urls: Vec<LinkRow> = db_query!(
+                "list_deleted_urls",
+                sqlx::query_as(
+                    r#"
+                    SELECT id, url, clicks, created_at, deleted_at, tags, notes FROM urls
+                    WHERE deleted_at IS NOT NULL
+                    ORDER BY deleted_at DESC
+                    LIMIT ?1 OFFSET ?2
+                    "#,
+                )
+                .bind(i64::from(per_page))
+                .bind(offset),
+                |query| query.fetch_all(&self.db)
+            )
+            .await?;
+
+            let total = db_query!(
+                "list_deleted_urls",
+                sqlx::query_scalar(
+                    r#"
+                    SELECT COUNT(*) FROM urls WHERE deleted_at IS NOT NULL
+                    "#,
+                ),
+                |query| query.fetch_one(&self.db)
+            )
+            .await?;
+
+            Ok((urls.into_iter().map(UrlRecord::from).collect(), total))
+        })
+        .await
+    }
+
+    async fn record_audit(
+        &self,
+        action: AuditAction,
+        id: &str,
+        actor: &str,
+    ) -> Result<(), TinyUrlError> {
+        self.query("record_audit", async {
+            db_query!(
+                "record_audit",
+                sqlx::query(
+                    r#"
+                    INSERT INTO audit_log (action, url_id, actor) VALUES (?1, ?2, ?3)
+                    "#,
+                )
+                .bind(action)
+                .bind(id)
+                .bind(actor),
+                |query| query.execute(&self.db)
+            )
+            .await?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list_audit(
+        &self,
+        id: Option<&str>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        page: u32,
+        per_page: u32,
+    ) -> Result<(Vec<AuditEntry>, i64), TinyUrlError> {
+        self.query("list_audit", async {
+            let offset = i64::from(page.saturating_sub(1)) * i64::from(per_page);
+
+            let entries = db_query!(
+                "list_audit",
+                sqlx::query_as(
+                    r#"
+                    SELECT id, action, url_id, actor, timestamp FROM audit_log
+                    WHERE (?1 IS NULL OR url_id = ?1)
+                      AND (?2 IS NULL OR timestamp >= ?2)
+                      AND (?3 IS NULL OR timestamp < ?3)
+                    ORDER BY timestamp DESC, id DESC
+                    LIMIT ?4 OFFSET ?5
+                    "#,
+                )
+                .bind(id)
+                .bind(from.map(timestamp))
+                .bind(to.map(timestamp))
+                .bind(i64::from(per_page))
+                .bind(offset),
+                |query| query.fetch_all(&self.db)
+            )
+            .await?;
+
+            let total = db_query!(
+                "list_audit",
+                sqlx::query_scalar(
+                    r#"
+                    SELECT COUNT(*) FROM audit_log
+                    WHERE (?1 IS NULL OR url_id = ?1)
+                      AND (?2 IS NULL OR timestamp >= ?2)
+                      AND (?3 IS NULL OR timestamp < ?3)
+                    "#,
+                )
+                .bind(id)
+                .bind(from.map(timestamp))
+                .bind(to.map(timestamp)),
+                |query| query.fetch_one(&self.db)
+            )
+            .await?;
+
+            Ok((entries, total))
+        })
+        .await
+    }
+
+    async fn import(&self, links: &[(String, String)]) -> Result<Vec<String>, TinyUrlError> {
+        self.query("import", async {
+            let mut tx = self.db.begin().await?;
+            let mut imported = Vec::new();
+
+            for (id, url) in links {
+                let res: Option<String> = db_query!(
+                    "import",
+                    sqlx::query_scalar(
+                        r#"
+                        INSERT INTO urls (id, url) VALUES (?1, ?2)
+                        ON CONFLICT DO NOTHING
+                        RETURNING id
+                        "#,
+                    )
+                    .bind(id)
+                    .bind(url),
+                    |query| query.fetch_optional(&mut *tx)
+                )
+                .await?;
+
+                imported.extend(res);
+            }
+
+            tx.commit().await?;
+
+            Ok(imported)
+        })
+        .await
+    }
+
+    async fn bulk_insert(&self, links: &[(String, String)]) -> Result<u64, TinyUrlError> {
+        self.query("bulk_insert", async {
+            // SQLite has no `COPY`, but inserts in one transaction are nearly as fast, and
+            // dropping it on a conflict rolls back the rows inserted before
+            let mut tx = self.db.begin().await?;
+
+            for (id, url) in links {
+                db_query!(
+                    "bulk_insert",
+                    sqlx::query(
+                        r#"
+                        INSERT INTO urls (id, url) VALUES (?1, ?2)
+                        "#,
+                    )
+                    .bind(id)
+                    .bind(url),
+                    |query| query.execute(&mut *tx)
+                )
+                .await
+                .map_err(|e| insert_error(e, id, url))?;
+            }
+
+            tx.commit().await?;
+
+            Ok(links.len() as u64)
+        })
+        .await
+    }
+
+    fn export(&self) -> BoxStream<'static, Result<ExportRecord, TinyUrlError>> {
+        // the row stream borrows the pool, so it is driven by a task that owns a handle to it
+        let (tx, rx) = mpsc::channel(EXPORT_BUFFER);
+        let db = self.db.clone();
+
+        // a stream cannot be instrumented, so the span covers the task driving it
+        tokio::spawn(db_query!(
+            "export",
+            sqlx::query_as::<_, ExportRow>(
+                r#"
+                SELECT id, url, clicks, created_at, expires_at, redirect_type, max_clicks,
+                       deleted_at, tags, notes
+                FROM urls
+                WHERE reserved_until IS NULL
+                ORDER BY created_at
+                "#,
+            ),
+            |query| async move {
+                let rows = query.fetch(&db).map(|row| row.map(ExportRecord::from));
+                forward(rows.boxed(), tx).await
+            }
+        ));
+
+        received(rx)
+    }
+
+    fn sitemap(
+        &self,
+        page: u32,
+        per_page: u32,
+    ) -> BoxStream<'static, Result<(String, DateTime<Utc>), TinyUrlError>> {
+        let offset = i64::from(page.saturating_sub(1)) * i64::from(per_page);
+        let (tx, rx) = mpsc::channel(EXPORT_BUFFER);
+        let db = self.db.clone();
+
+        tokio::spawn(db_query!(
+            "sitemap",
+            sqlx::query_as::<_, (String, DateTime<Utc>)>(
+                r#"
+                SELECT id, created_at FROM urls
+                WHERE deleted_at IS NULL
+                  AND reserved_until IS NULL
+                  AND (expires_at IS NULL OR expires_at > ?3)
+                  AND password_hash IS NULL
+                  AND NOT private
+                ORDER BY created_at, id
+                LIMIT ?1 OFFSET ?2
+                "#,
+            )
+            .bind(i64::from(per_page))
+            .bind(offset)
+            .bind(timestamp(Utc::now())),
+            // the row stream borrows the pool, like the one of `export`
+            |query| async move { forward(query.fetch(&db), tx).await }
+        ));
+
+        received(rx)
+    }
+
+    async fn create_webhook(
+        &self,
+        target_url: &str,
+        req: &WebhookRequest,
+    ) -> Result<Webhook, TinyUrlError> {
+        self.query("create_webhook", async {
+            let webhook = db_query!(
+                "create_webhook",
+                sqlx::query_as(
+                    r#"
+                    INSERT INTO webhooks (target_url, event, url_id) VALUES (?1, ?2, ?3)
+                    RETURNING id, target_url, event, url_id, created_at
+                    "#,
+                )
+                .bind(target_url)
+                .bind(req.event)
+                .bind(req.url_id.as_deref()),
+                |query| query.fetch_one(&self.db)
+            )
+            .await?;
+
+            Ok(webhook)
+        })
+        .await
+    }
+
+    async fn list_webhooks(&self, event: WebhookEvent) -> Result<Vec<Webhook>, TinyUrlError> {
+        self.query("list_webhooks", async {
+            let webhooks = db_query!(
+                "list_webhooks",
+                sqlx::query_as(
+                    r#"
+                    SELECT id, target_url, event, url_id, created_at FROM webhooks
+                    WHERE event = ?1
+                    "#,
+                )
+                .bind(event),
+                |query| query.fetch_all(&self.db)
+            )
+            .await?;
+
+            Ok(webhooks)
+        })
+        .await
+    }
+
+    async fn api_key_limit(&self, key: &str) -> Result<Option<u64>, TinyUrlError> {
+        self.query("api_key_limit", async {
+            let limit: Option<i64> = db_query!(
+                "api_key_limit",
+                sqlx::query_scalar(
+                    r#"
+                    SELECT requests_per_minute FROM api_keys WHERE key = ?1
+                    "#,
+                )
+                .bind(key),
+                |query| query.fetch_optional(&self.db)
+            )
+            .await?;
+
+            Ok(limit.map(|limit| limit.max(0) as u64))
+        })
+        .await
+    }
+
+    async fn ping(&self) -> Result<(), TinyUrlError> {
+        self.query("ping", async {
+            db_query!("ping", sqlx::query("SELECT 1"), |query| {
+                query.execute(&self.db)
+            })
+            .await
+            .map_err(TinyUrlError::HealthCheckFailed)?;
+
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DurationRound;
+
+    use crate::config_file::Vars;
+
+    /// A store on an in-memory database of its own, shared by the connections of its pool.
+    async fn store() -> SqliteStore {
+        let url = format!("sqlite:file:{}?mode=memory&cache=shared", Uuid::new_v4());
+        let vars = Vars::default().with_env(&[("DATABASE_URL", &url)]);
+        SqliteStore::connect(&Config::from_vars(&vars))
+            .await
+            .unwrap()
+    }
+
+    fn request(url: &str) -> ShortenRequest {
+        ShortenRequest {
+            url: url.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn shorten_reuses_code_of_plain_link_only() {
+        let store = store().await;
+        let req = request("https://example.com/");
+
+        assert_eq!(
+            store.shorten("abc", &req.url, &req).await.unwrap(),
+            ("abc".to_string(), true)
+        );
+        assert_eq!(
+            store.shorten("def", &req.url, &req).await.unwrap(),
+            ("abc".to_string(), false)
+        );
+
+        let expiring = ShortenRequest {
+            ttl_seconds: Some(60),
+            ..request("https://example.com/")
+        };
+        assert_eq!(
+            store.shorten("ghi", &req.url, &expiring).await.unwrap(),
+            ("ghi".to_string(), true)
+        );
+        let err = store.shorten("abc", &req.url, &expiring).await.unwrap_err();
+        assert!(matches!(err, TinyUrlError::CodeAlreadyTaken(_)));
+    }
+
+    #[tokio::test]
+    async fn retires_link_after_max_clicks() {
+        let store = store().await;
+        let req = ShortenRequest {
+            max_clicks: Some(1),
+            ..request("https://example.com/")
+        };
+        store.shorten("abc", &req.url, &req).await.unwrap();
+
+        assert!(store.get_url_by_id("abc", None, false).await.is_ok());
+        let err = store.get_url_by_id("abc", None, false).await.unwrap_err();
+        assert!(matches!(err, TinyUrlError::LinkExpired(_)));
+    }
+
+    #[tokio::test]
+    async fn checks_referer_before_password() {
+        let store = store().await;
+        let req = ShortenRequest {
+            allowed_referers: Some(vec!["https://blog.example".to_string()]),
+            password_hash: Some("hash".to_string()),
+            ..request("https://example.com/")
+        };
+        store.shorten("abc", &req.url, &req).await.unwrap();
+
+        let err = store.peek_url("abc", None).await.unwrap_err();
+        assert!(matches!(err, TinyUrlError::RefererNotAllowed(_)));
+        let err = store
+            .get_url_by_id("abc", Some("https://other.example"), true)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, TinyUrlError::RefererNotAllowed(_)));
+        let err = store
+            .get_url_by_id("abc", Some("https://blog.example"), false)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, TinyUrlError::PasswordRequired(_)));
+
+        let target = store
+            .get_url_by_id("abc", Some("https://blog.example"), true)
+            .await
+            .unwrap();
+        assert_eq!(target.allowed_referers, ["https://blog.example"]);
+        assert!(target.password_protected);
+        assert_eq!(store.get_stats("abc", true).await.unwrap().clicks, 1);
+    }
+
+    #[tokio::test]
+    async fn deleted_links_are_tombstoned_until_purged() {
+        let store = store().await;
+        let req = request("https://example.com/");
+        store.shorten("abc", &req.url, &req).await.unwrap();
+        store.delete_url("abc").await.unwrap();
+
+        assert!(store.peek_url("abc", None).await.is_err());
+        assert_eq!(store.list_deleted_urls(1, 10).await.unwrap().1, 1);
+
+        // stored times have millisecond precision
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        assert_eq!(store.purge_deleted(Duration::ZERO).await.unwrap(), 1);
+        assert_eq!(store.list_deleted_urls(1, 10).await.unwrap().1, 0);
+    }
+
+    #[tokio::test]
+    async fn lists_links_by_tag_after_cursor() {
+        let store = store().await;
+        for (id, url) in [("a", "https://a.example/"), ("b", "https://b.example/")] {
+            let req = ShortenRequest {
+                tags: Some(vec!["marketing".to_string()]),
+                ..request(url)
+            };
+            store.shorten(id, url, &req).await.unwrap();
+        }
+        let req = request("https://c.example/");
+        store.shorten("c", &req.url, &req).await.unwrap();
+
+        let first = store.list_urls(None, 1, Some("marketing")).await.unwrap();
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].tags, ["marketing"]);
+
+        let after = Cursor::after(&first[0]);
+        let rest = store
+            .list_urls(Some(&after), 10, Some("marketing"))
+            .await
+            .unwrap();
+        let mut ids: Vec<_> = first.iter().chain(&rest).map(|r| r.id.as_str()).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, ["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn sums_global_stats() {
+        let store = store().await;
+        let req = ShortenRequest {
+            ttl_seconds: Some(60),
+            ..request("https://a.example/")
+        };
+        store.shorten("a", &req.url, &req).await.unwrap();
+        let req = request("https://b.example/");
+        store.shorten("b", &req.url, &req).await.unwrap();
+        store.get_url_by_id("b", None, false).await.unwrap();
+        store.get_url_by_id("b", None, false).await.unwrap();
+
+        let stats = store.global_stats().await.unwrap();
+        assert_eq!(stats.total_urls, 2);
+        assert_eq!(stats.total_clicks, 2);
+        assert_eq!(stats.urls_created_last_24h, 2);
+        assert_eq!(stats.urls_expiring_next_24h, 1);
+        assert_eq!(stats.top_10_urls[0].id, "b");
+    }
+
+    #[tokio::test]
+    async fn counts_clicks_per_bucket() {
+        let store = store().await;
+        let req = request("https://example.com/");
+        store.shorten("abc", &req.url, &req).await.unwrap();
+        let info = ClickInfo {
+            ip: [127, 0, 0, 1].into(),
+            user_agent: None,
+            referer: None,
+        };
+        store.record_click("abc", &info).await.unwrap();
+        store.record_click("abc", &info).await.unwrap();
+
+        let now = Utc::now();
+        let buckets = store
+            .click_timeseries(
+                "abc",
+                Granularity::Day,
+                now - TimeDelta::days(1),
+                now + TimeDelta::days(1),
+            )
+            .await
+            .unwrap();
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(buckets[0].clicks, 2);
+        assert_eq!(
+            buckets[0].bucket,
+            now.duration_trunc(TimeDelta::days(1)).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn bulk_insert_reports_conflicts_without_inserting() {
+        let store = store().await;
+        let req = request("https://example.com/");
+        store.shorten("abc", &req.url, &req).await.unwrap();
+        let links = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            pairs
+                .iter()
+                .map(|(id, url)| (id.to_string(), url.to_string()))
+                .collect()
+        };
+
+        let err = store
+            .bulk_insert(&links(&[
+                ("new", "https://a.example/"),
+                ("abc", "https://b.example/"),
+            ]))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, TinyUrlError::CodeAlreadyTaken(id) if id == "abc"));
+        let err = store
+            .bulk_insert(&links(&[("new", "https://example.com/")]))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, TinyUrlError::UrlAlreadyExists(_)));
+        assert!(store.peek_url("new", None).await.is_err());
+
+        let inserted = store
+            .bulk_insert(&links(&[("new", "https://a.example/")]))
+            .await
+            .unwrap();
+        assert_eq!(inserted, 1);
+    }
+
+    #[tokio::test]
+    async fn reports_pool_timeouts_as_exhausted() {
+        let url = format!("sqlite:file:{}?mode=memory&cache=shared", Uuid::new_v4());
+        let vars = Vars::default().with_env(&[
+            ("DATABASE_URL", &url),
+            ("TINYURL_DB_MAX_CONNECTIONS", "1"),
+            ("TINYURL_DB_CONNECT_TIMEOUT_SECS", "1"),
+        ]);
+        let store = SqliteStore::connect(&Config::from_vars(&vars))
+            .await
+            .unwrap();
+
+        let held = store.db.acquire().await.unwrap();
+        let err = store.peek_url("abc", None).await.unwrap_err();
+        assert!(matches!(err, TinyUrlError::PoolExhausted));
+
+        drop(held);
+        let err = store.peek_url("abc", None).await.unwrap_err();
+        assert!(matches!(err, TinyUrlError::IdNotFound(_)));
+    }
+}
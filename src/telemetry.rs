@@ -0,0 +1,183 @@
+use std::{str::FromStr, time::Instant};
+
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+use metrics::histogram;
+use metrics_exporter_prometheus::{BuildError, PrometheusBuilder, PrometheusHandle};
+#[cfg(feature = "otel")]
+use opentelemetry::{
+    global,
+    trace::{
+        SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState, TracerProvider,
+    },
+    Context, KeyValue,
+};
+#[cfg(feature = "otel")]
+use opentelemetry_sdk::{runtime, trace::Config as TraceConfig, Resource};
+use tracing::{info_span, Span};
+#[cfg(feature = "otel")]
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, Layer};
+
+use crate::{Config, TinyUrlError};
+
+/// Output format of log lines, chosen with `TINYURL_LOG_FORMAT`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line, for log aggregators.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format: {}", s)),
+        }
+    }
+}
+
+/// Installs the global tracing subscriber, logging at `TINYURL_LOG_LEVEL` in `TINYURL_LOG_FORMAT`.
+///
+/// With the `otel` feature and `OTEL_EXPORTER_OTLP_ENDPOINT` set, spans are also exported
+/// over OTLP; the exporter reads the rest of its settings from the standard `OTEL_*` variables.
+pub fn init_tracing(config: &Config) -> Result<(), TinyUrlError> {
+    let fmt = match config.log_format {
+        LogFormat::Text => fmt::Layer::new().boxed(),
+        LogFormat::Json => fmt::Layer::new().json().boxed(),
+    }
+    .with_filter(config.log_level);
+
+    #[cfg(feature = "otel")]
+    let otel = match std::env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT") {
+        Some(_) => {
+            let provider = opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(opentelemetry_otlp::new_exporter().tonic())
+                .with_trace_config(TraceConfig::default().with_resource(Resource::new([
+                    KeyValue::new("service.name", env!("CARGO_PKG_NAME")),
+                ])))
+                .install_batch(runtime::Tokio)?;
+            let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+            global::set_tracer_provider(provider);
+
+            Some(
+                tracing_opentelemetry::layer()
+                    .with_tracer(tracer)
+                    .with_filter(config.log_level),
+            )
+        }
+        None => None,
+    };
+
+    let registry = tracing_subscriber::registry().with(fmt);
+    #[cfg(feature = "otel")]
+    let registry = registry.with(otel);
+    registry.init();
+
+    Ok(())
+}
+
+/// Flushes spans still buffered by the OTLP exporter.
+#[cfg(feature = "otel")]
+pub fn shutdown_tracing() {
+    global::shutdown_tracer_provider();
+}
+
+/// Installs the global Prometheus recorder and returns a handle to render it.
+pub fn install_recorder() -> Result<PrometheusHandle, BuildError> {
+    PrometheusBuilder::new().install_recorder()
+}
+
+/// Middleware recording `request_duration_seconds`, labelled by the matched route.
+pub async fn track_duration(req: Request, next: Next) -> Response {
+    // label with the route template rather than the raw path to bound cardinality
+    let handler = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let start = Instant::now();
+    let resp = next.run(req).await;
+
+    histogram!("request_duration_seconds", "handler" => handler)
+        .record(start.elapsed().as_secs_f64());
+
+    resp
+}
+
+/// Root span of a request, tagged with the `X-Request-Id` set by `SetRequestIdLayer`.
+pub fn request_span(req: &Request) -> Span {
+    let request_id = req
+        .headers()
+        .get("x-request-id")
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default();
+
+    let span = info_span!(
+        "request",
+        method = %req.method(),
+        uri = %req.uri(),
+        request_id = %request_id,
+    );
+
+    #[cfg(feature = "otel")]
+    if let Some(parent) = request_id_context(request_id) {
+        span.set_parent(parent);
+    }
+
+    span
+}
+
+/// Remote parent whose trace ID is the (UUID) request ID, so that a trace can be found from
+/// the `X-Request-Id` a client reports.
+#[cfg(feature = "otel")]
+fn request_id_context(request_id: &str) -> Option<Context> {
+    let hex = request_id.replace('-', "");
+    let trace_id = TraceId::from_hex(&hex).ok()?;
+    let span_id = SpanId::from_hex(hex.get(16..)?).ok()?;
+
+    let parent = SpanContext::new(
+        trace_id,
+        span_id,
+        TraceFlags::SAMPLED,
+        true,
+        TraceState::default(),
+    );
+    parent
+        .is_valid()
+        .then(|| Context::new().with_remote_span_context(parent))
+}
+
+/// Middleware adding the W3C `traceparent` of the request span to the response.
+#[cfg(feature = "otel")]
+pub async fn traceparent(req: Request, next: Next) -> Response {
+    let mut resp = next.run(req).await;
+
+    let context = Span::current().context();
+    let span = context.span();
+    let span_context = span.span_context();
+    if span_context.is_valid() {
+        let value = format!(
+            "00-{}-{}-{:02x}",
+            span_context.trace_id(),
+            span_context.span_id(),
+            span_context.trace_flags().to_u8()
+        );
+        if let Ok(value) = value.parse() {
+            resp.headers_mut().insert("traceparent", value);
+        }
+    }
+
+    resp
+}
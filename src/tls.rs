@@ -0,0 +1,53 @@
+use std::{io, net::SocketAddr, sync::Arc};
+
+use axum::Router;
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use tokio::{net::lookup_host, sync::Notify};
+use tracing::{info, warn};
+
+use crate::{shutdown_signal, Config, TinyUrlError, SHUTDOWN_TIMEOUT};
+
+/// Serves `app` over HTTPS on `TINYURL_HOST` and `TINYURL_TLS_PORT`.
+pub async fn serve(
+    app: Router,
+    config: &Config,
+    cert: &str,
+    key: &str,
+) -> Result<(), TinyUrlError> {
+    let rustls = RustlsConfig::from_pem_file(cert, key).await?;
+
+    let addr = lookup_host((config.host.as_str(), config.tls_port))
+        .await?
+        .next()
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                format!("cannot resolve {}", config.host),
+            )
+        })?;
+
+    if config.base_url.starts_with("http://") {
+        warn!(
+            "TLS is active but TINYURL_BASE_URL is {}, short links will use plain HTTP",
+            config.base_url
+        );
+    }
+
+    // axum-server drains in-flight requests itself and closes what is left after the timeout
+    let handle = Handle::new();
+    tokio::spawn({
+        let handle = handle.clone();
+        async move {
+            shutdown_signal(Arc::new(Notify::new())).await;
+            handle.graceful_shutdown(Some(SHUTDOWN_TIMEOUT));
+        }
+    });
+
+    info!("Listening on: https://{}", addr);
+    axum_server::bind_rustls(addr, rustls)
+        .handle(handle)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use std::{
+    fs, io,
+    net::{Ipv4Addr, SocketAddr},
+    os::unix::fs::FileTypeExt,
+    sync::Arc,
+};
+
+use axum::{extract::ConnectInfo, Extension, Router};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::{conn::auto, graceful::GracefulShutdown},
+    service::TowerToHyperService,
+};
+use tokio::{net::UnixListener, sync::Notify};
+use tracing::{info, warn};
+
+use crate::{shutdown_signal, TinyUrlError, SHUTDOWN_TIMEOUT};
+
+/// Serves `app` on the Unix domain socket at `path`, replacing a stale socket file. Any
+/// other file at `path` is left alone and fails startup.
+///
+/// Socket peers have no IP address, so they all appear as `127.0.0.1` to the rate limiter and
+/// the click log.
+pub async fn serve(app: Router, path: &str) -> Result<(), TinyUrlError> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(TinyUrlError::InvalidConfig(format!(
+                "cannot listen on {}: it exists and is not a socket",
+                path
+            )))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    let listener = UnixListener::bind(path)?;
+    info!("Listening on: unix:{}", path);
+
+    let peer = ConnectInfo(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)));
+    let service = TowerToHyperService::new(app.layer(Extension(peer)));
+    let graceful = GracefulShutdown::new();
+
+    let signal = shutdown_signal(Arc::new(Notify::new()));
+    tokio::pin!(signal);
+
+    loop {
+        tokio::select! {
+            conn = listener.accept() => {
+                let stream = match conn {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        warn!("Failed to accept connection: {}", e);
+                        continue;
+                    }
+                };
+
+                let conn = auto::Builder::new(TokioExecutor::new())
+                    .serve_connection(TokioIo::new(stream), service.clone())
+                    .into_owned();
+                let conn = graceful.watch(conn);
+
+                tokio::spawn(async move {
+                    if let Err(e) = conn.await {
+                        warn!("Connection error: {}", e);
+                    }
+                });
+            }
+            _ = &mut signal => break,
+        }
+    }
+
+    drop(listener);
+
+    // in-flight requests get SHUTDOWN_TIMEOUT to drain
+    tokio::select! {
+        _ = graceful.shutdown() => {}
+        _ = tokio::time::sleep(SHUTDOWN_TIMEOUT) => {
+            warn!("Graceful shutdown timed out after {:?}", SHUTDOWN_TIMEOUT);
+        }
+    }
+
+    fs::remove_file(path)?;
+
+    Ok(())
+}
@@ -0,0 +1,124 @@
+//! Notification of webhooks registered at `POST /admin/webhooks` when links are created or
+//! clicked.
+
+use std::{sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use metrics::counter;
+use moka::future::Cache;
+use reqwest::Client;
+use serde::Serialize;
+use tracing::{error, warn};
+
+use crate::{store::UrlStore, ClickInfo, TinyUrlError, Webhook, WebhookEvent};
+
+/// How long a target gets to answer a delivery.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the webhooks of an event are kept in memory, so that clicks do not query them.
+const WEBHOOK_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// JSON body POSTed to webhook targets.
+#[derive(Debug, Serialize)]
+struct Payload<'a> {
+    event: WebhookEvent,
+    url_id: &'a str,
+    timestamp: DateTime<Utc>,
+    /// Client following the link, for `clicked` events.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    click: Option<&'a ClickInfo>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Notifier {
+    client: Client,
+    webhooks: Cache<WebhookEvent, Arc<Vec<Webhook>>>,
+}
+
+impl Notifier {
+    pub fn new() -> Result<Self, TinyUrlError> {
+        let client = Client::builder()
+            .timeout(DELIVERY_TIMEOUT)
+            .build()
+            .map_err(|e| TinyUrlError::InvalidConfig(e.to_string()))?;
+
+        Ok(Self {
+            client,
+            webhooks: Cache::builder().time_to_live(WEBHOOK_CACHE_TTL).build(),
+        })
+    }
+
+    /// Forgets the cached webhooks of `event`, so that a new one is notified right away.
+    pub async fn invalidate(&self, event: WebhookEvent) {
+        self.webhooks.invalidate(&event).await;
+    }
+
+    /// Delivers `event` on link `id` to every webhook subscribed to it, in the background.
+    pub fn notify<S: UrlStore>(
+        &self,
+        store: &S,
+        event: WebhookEvent,
+        id: &str,
+        click: Option<ClickInfo>,
+    ) {
+        let notifier = self.clone();
+        let store = store.clone();
+        let id = id.to_string();
+        let timestamp = Utc::now();
+
+        tokio::spawn(async move {
+            let webhooks = match notifier.webhooks(&store, event).await {
+                Ok(webhooks) => webhooks,
+                Err(e) => {
+                    error!("Failed to look up webhooks for {}: {}", id, e);
+                    return;
+                }
+            };
+
+            let payload = Payload {
+                event,
+                url_id: &id,
+                timestamp,
+                click: click.as_ref(),
+            };
+            for webhook in webhooks.iter().filter(|w| w.matches(&id)) {
+                notifier.deliver(&webhook.target_url, &payload).await;
+            }
+        });
+    }
+
+    async fn webhooks<S: UrlStore>(
+        &self,
+        store: &S,
+        event: WebhookEvent,
+    ) -> Result<Arc<Vec<Webhook>>, TinyUrlError> {
+        if let Some(webhooks) = self.webhooks.get(&event).await {
+            return Ok(webhooks);
+        }
+
+        let webhooks = Arc::new(store.list_webhooks(event).await?);
+        self.webhooks.insert(event, webhooks.clone()).await;
+
+        Ok(webhooks)
+    }
+
+    /// POSTs `payload` to `target`, trying a second time if the first attempt fails.
+    async fn deliver(&self, target: &str, payload: &Payload<'_>) {
+        let attempt = || async {
+            self.client
+                .post(target)
+                .json(payload)
+                .send()
+                .await
+                .and_then(|resp| resp.error_for_status())
+        };
+
+        if let Err(e) = attempt().await {
+            warn!("Retrying webhook delivery to {}: {}", target, e);
+            if let Err(e) = attempt().await {
+                counter!("webhook_failures_total").increment(1);
+                error!("Failed to deliver webhook to {}: {}", target, e);
+            }
+        }
+    }
+}
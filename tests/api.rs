@@ -0,0 +1,227 @@
+//! End-to-end tests running the server binary against a throwaway PostgreSQL container.
+//!
+//! They need a Docker daemon, so they are ignored by default: run them with
+//! `cargo test -- --ignored`.
+
+use std::{
+    net::TcpListener,
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
+
+use reqwest::{redirect::Policy, Client, StatusCode};
+use serde_json::{json, Value};
+use testcontainers_modules::{
+    postgres::Postgres,
+    testcontainers::{runners::AsyncRunner, ContainerAsync},
+};
+
+const ADMIN_TOKEN: &str = "integration-test-token";
+
+/// How long the server gets to connect to the database and run migrations.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A server process with its own database, both torn down on drop.
+struct TestServer {
+    _db: ContainerAsync<Postgres>,
+    process: Child,
+    base_url: String,
+    client: Client,
+}
+
+impl TestServer {
+    async fn start() -> Self {
+        let db = Postgres::default()
+            .start()
+            .await
+            .expect("failed to start PostgreSQL container");
+        let database_url = format!(
+            "postgres://postgres:postgres@{}:{}/postgres",
+            db.get_host().await.unwrap(),
+            db.get_host_port_ipv4(5432).await.unwrap()
+        );
+
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap();
+        let base_url = format!("http://{}", addr);
+
+        let process = Command::new(env!("CARGO_BIN_EXE_tinyurl"))
+            .env("DATABASE_URL", database_url)
+            .env("TINYURL_LISTEN_ADDR", addr.to_string())
+            .env("TINYURL_BASE_URL", &base_url)
+            .env("TINYURL_ADMIN_TOKEN", ADMIN_TOKEN)
+            .env("TINYURL_RATE_LIMIT", "0")
+            .env("TINYURL_LOG_LEVEL", "warn")
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("failed to start server");
+
+        let server = Self {
+            _db: db,
+            process,
+            base_url,
+            client: Client::builder().redirect(Policy::none()).build().unwrap(),
+        };
+        server.wait_until_ready().await;
+
+        server
+    }
+
+    async fn wait_until_ready(&self) {
+        let deadline = tokio::time::Instant::now() + STARTUP_TIMEOUT;
+        while tokio::time::Instant::now() < deadline {
+            let resp = self.client.get(self.url("/health/ready")).send().await;
+            if resp.is_ok_and(|resp| resp.status().is_success()) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+        panic!("server not ready after {:?}", STARTUP_TIMEOUT);
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// Shortens `url`, returning the response status and body.
+    async fn shorten(&self, url: &str) -> (StatusCode, Value) {
+        let resp = self
+            .client
+            .post(self.url("/"))
+            .json(&json!({ "url": url }))
+            .send()
+            .await
+            .unwrap();
+
+        (resp.status(), resp.json().await.unwrap())
+    }
+
+    /// Shortens `url`, returning its short code.
+    async fn shorten_id(&self, url: &str) -> String {
+        let (status, body) = self.shorten(url).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let short = body["url"].as_str().unwrap();
+        short.rsplit('/').next().unwrap().to_string()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs Docker"]
+async fn shortens_url() {
+    let server = TestServer::start().await;
+
+    let (status, body) = server.shorten("https://www.postgresql.org/docs/").await;
+
+    assert_eq!(status, StatusCode::CREATED);
+    let short = body["url"].as_str().unwrap();
+    assert!(short.starts_with(&server.base_url), "{}", short);
+}
+
+#[tokio::test]
+#[ignore = "needs Docker"]
+async fn redirects_to_url() {
+    let server = TestServer::start().await;
+    let id = server.shorten_id("https://www.postgresql.org/docs/").await;
+
+    let resp = server
+        .client
+        .get(server.url(&format!("/{}", id)))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(resp.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(
+        resp.headers()["location"],
+        "https://www.postgresql.org/docs"
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs Docker"]
+async fn returns_existing_link_for_same_url() {
+    let server = TestServer::start().await;
+
+    let (_, first) = server.shorten("https://www.postgresql.org/docs/").await;
+    let (status, second) = server.shorten("https://www.postgresql.org/docs/").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(first["url"], second["url"]);
+}
+
+#[tokio::test]
+#[ignore = "needs Docker"]
+async fn rejects_invalid_url() {
+    let server = TestServer::start().await;
+
+    let (status, body) = server.shorten("not a url").await;
+
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["code"], "invalid_url");
+}
+
+#[tokio::test]
+#[ignore = "needs Docker"]
+async fn answers_unknown_id_with_not_found() {
+    let server = TestServer::start().await;
+
+    let resp = server.client.get(server.url("/nope")).send().await.unwrap();
+
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["code"], "id_not_found");
+}
+
+#[tokio::test]
+#[ignore = "needs Docker"]
+async fn deletes_link() {
+    let server = TestServer::start().await;
+    let id = server.shorten_id("https://www.postgresql.org/docs/").await;
+    let path = format!("/{}", id);
+
+    let resp = server
+        .client
+        .delete(server.url(&path))
+        .bearer_auth(ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+    let resp = server.client.get(server.url(&path)).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+#[ignore = "needs Docker"]
+async fn counts_clicks_in_stats() {
+    let server = TestServer::start().await;
+    let id = server.shorten_id("https://www.postgresql.org/docs/").await;
+
+    server
+        .client
+        .get(server.url(&format!("/{}", id)))
+        .send()
+        .await
+        .unwrap();
+    let resp = server
+        .client
+        .get(server.url(&format!("/{}/stats", id)))
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = resp.json().await.unwrap();
+    assert_eq!(body["id"], id.as_str());
+    assert_eq!(body["clicks"], 1);
+}